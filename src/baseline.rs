@@ -0,0 +1,247 @@
+use core::fmt;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    io::{self, Write},
+};
+
+use petgraph::graph::{Graph, NodeIndex};
+use serde::{Deserialize, Serialize};
+
+use crate::{Edge, Local, Max, Node};
+
+/// Results of the stack usage analysis in a form that can be saved and compared against later
+#[derive(Deserialize, Serialize)]
+pub struct Analysis {
+    pub functions: Vec<Function>,
+    /// Names of the functions that form each cycle
+    pub cycles: Vec<Vec<String>>,
+    /// Patterns of the functions that were left out of the analysis
+    #[serde(default)]
+    pub excluded: Vec<String>,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct Function {
+    pub name: String,
+    /// `None` if unknown
+    pub local: Option<u64>,
+    /// `None` if the max stack usage analysis was not performed
+    pub max: Option<u64>,
+    /// Whether `max` is exact or a lower bound
+    pub exact: bool,
+    /// Whether `local` or `max` was given by the user
+    #[serde(default)]
+    pub assumed: bool,
+    pub address: Option<u64>,
+    pub location: Option<String>,
+}
+
+impl Analysis {
+    pub(crate) fn new(
+        g: &Graph<Node, Edge>,
+        cycles: &[Vec<NodeIndex>],
+        excluded: &[String],
+    ) -> Self {
+        let name = |node: &Node| rustc_demangle::demangle(&node.name).to_string();
+
+        let functions = g
+            .node_weights()
+            .map(|node| {
+                let (max, exact) = match node.max {
+                    Some(Max::Exact(n)) => (Some(n), true),
+                    Some(Max::LowerBound(n)) => (Some(n), false),
+                    None => (None, false),
+                };
+
+                Function {
+                    name: name(node),
+                    local: match node.local {
+                        Local::Exact(n) => Some(n),
+                        Local::Unknown => None,
+                    },
+                    max,
+                    exact,
+                    assumed: node.assumed,
+                    address: node.address,
+                    location: node.loc.as_ref().map(|loc| loc.to_string()),
+                }
+            })
+            .collect();
+
+        let cycles = cycles
+            .iter()
+            .map(|cycle| {
+                let mut names = cycle.iter().map(|idx| name(&g[*idx])).collect::<Vec<_>>();
+                names.sort();
+                names
+            })
+            .collect();
+
+        Analysis {
+            functions,
+            cycles,
+            excluded: excluded.to_owned(),
+        }
+    }
+
+    // indexes the functions by name; if several functions share a name (e.g. `?` nodes) the one
+    // with the largest stack usage is kept
+    fn by_name(&self) -> BTreeMap<&str, &Function> {
+        let mut map = BTreeMap::<&str, &Function>::new();
+        for f in &self.functions {
+            let entry = map.entry(&f.name).or_insert(f);
+            if f.max > entry.max {
+                *entry = f;
+            }
+        }
+        map
+    }
+}
+
+/// Prints the differences between `old` and `new` and returns `true` if `new` regressed
+///
+/// A regression is a function whose maximum stack usage grew by more than `threshold` bytes, a
+/// function whose maximum stack usage is no longer exact, a new function whose maximum stack usage
+/// is only a lower bound or a new cycle
+pub fn diff(old: &Analysis, new: &Analysis, threshold: u64) -> io::Result<bool> {
+    let stdout = io::stdout();
+    let mut stdout = stdout.lock();
+
+    let old_fns = old.by_name();
+    let new_fns = new.by_name();
+    let mut regressions = 0;
+
+    if old.excluded != new.excluded {
+        writeln!(
+            stdout,
+            "note: the analyses exclude different functions: [{}] -> [{}]\n",
+            old.excluded.join(", "),
+            new.excluded.join(", ")
+        )?;
+    }
+
+    writeln!(stdout, "Changed functions (local, max)")?;
+    for (name, new) in &new_fns {
+        let old = if let Some(old) = old_fns.get(name) {
+            old
+        } else {
+            continue;
+        };
+
+        if old.local == new.local && old.max == new.max && old.exact == new.exact {
+            continue;
+        }
+
+        let grew = match (old.max, new.max) {
+            (Some(old), Some(new)) => new > old + threshold,
+            _ => false,
+        };
+        let unbounded = old.exact && !new.exact && new.max.is_some();
+        let flag = if grew || unbounded {
+            regressions += 1;
+            "!"
+        } else {
+            " "
+        };
+
+        writeln!(
+            stdout,
+            "{} {}: {} -> {} ({}), {} -> {} ({}){}",
+            flag,
+            name,
+            Opt(old.local),
+            Opt(new.local),
+            Delta(old.local, new.local),
+            Bound(old.max, old.exact),
+            Bound(new.max, new.exact),
+            Delta(old.max, new.max),
+            if unbounded { " newly unbounded" } else { "" },
+        )?;
+    }
+
+    writeln!(stdout, "\nNew functions")?;
+    for (name, new) in &new_fns {
+        if !old_fns.contains_key(name) {
+            let unbounded = !new.exact && new.max.is_some();
+            let flag = if unbounded {
+                regressions += 1;
+                "!"
+            } else {
+                " "
+            };
+
+            writeln!(
+                stdout,
+                "{} {}: local {}, max {}{}",
+                flag,
+                name,
+                Opt(new.local),
+                Bound(new.max, new.exact),
+                if unbounded { " unbounded" } else { "" },
+            )?;
+        }
+    }
+
+    writeln!(stdout, "\nRemoved functions")?;
+    for name in old_fns.keys() {
+        if !new_fns.contains_key(name) {
+            writeln!(stdout, "  {}", name)?;
+        }
+    }
+
+    writeln!(stdout, "\nNew cycles")?;
+    let old_cycles = old.cycles.iter().collect::<BTreeSet<_>>();
+    for cycle in &new.cycles {
+        if !old_cycles.contains(cycle) {
+            regressions += 1;
+            writeln!(stdout, "! {}", cycle.join(", "))?;
+        }
+    }
+
+    if regressions != 0 {
+        writeln!(
+            stdout,
+            "\nerror: stack usage regressed in {} {} (threshold: {} bytes); see entries marked \
+             with `!`",
+            regressions,
+            if regressions == 1 { "entry" } else { "entries" },
+            threshold
+        )?;
+    }
+
+    Ok(regressions != 0)
+}
+
+struct Opt(Option<u64>);
+
+impl fmt::Display for Opt {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.0 {
+            Some(n) => write!(f, "{}", n),
+            None => f.write_str("?"),
+        }
+    }
+}
+
+struct Bound(Option<u64>, bool);
+
+impl fmt::Display for Bound {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Bound(Some(n), true) => write!(f, "{}", n),
+            Bound(Some(n), false) => write!(f, ">={}", n),
+            Bound(None, _) => f.write_str("?"),
+        }
+    }
+}
+
+struct Delta(Option<u64>, Option<u64>);
+
+impl fmt::Display for Delta {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Delta(Some(old), Some(new)) => write!(f, "{:+}", new as i64 - old as i64),
+            _ => f.write_str("?"),
+        }
+    }
+}
@@ -0,0 +1,164 @@
+use core::cmp;
+use std::{
+    borrow::Cow,
+    collections::BTreeMap,
+    io::{self, Write},
+};
+
+use log::warn;
+use petgraph::{
+    graph::{Graph, NodeIndex},
+    visit::Dfs,
+};
+
+use crate::{baseline::Analysis, max_of, max_value, Edge, Max, Node};
+
+/// Name of the node that represents the non-secure functions that secure code calls
+pub(crate) const NON_SECURE: &str = "(non-secure)";
+
+const ENTRY_PREFIX: &str = "__acle_se_";
+
+/// Returns the node that represents the non-secure functions, adding it to the graph if needed
+///
+/// Non-secure code runs on the non-secure stack so the node uses no (secure) stack
+pub(crate) fn non_secure<'a>(
+    g: &mut Graph<Node<'a>, Edge>,
+    indices: &mut BTreeMap<Cow<'a, str>, NodeIndex>,
+) -> NodeIndex {
+    if let Some(idx) = indices.get(NON_SECURE) {
+        return *idx;
+    }
+
+    let idx = g.add_node(Node(NON_SECURE, Some(0), true));
+    indices.insert(NON_SECURE.into(), idx);
+    idx
+}
+
+/// Whether this is the body of a secure entry function (`__acle_se_{name}`)
+pub(crate) fn is_entry(name: &str) -> bool {
+    name.starts_with(ENTRY_PREFIX)
+}
+
+/// The name of the body of the secure entry function `name`
+pub(crate) fn entry(name: &str) -> String {
+    format!("{}{}", ENTRY_PREFIX, name)
+}
+
+/// The secure stack usage of the entry functions of a secure image, taken from its analysis
+/// (`--format json`)
+pub(crate) struct Secure {
+    entries: BTreeMap<String, Max>,
+}
+
+impl Secure {
+    pub(crate) fn new(analysis: &Analysis) -> Self {
+        // the gateway (`SG` followed by a branch to the body) uses no stack so the stack usage of
+        // an entry function is the stack usage of its body (`__acle_se_{name}`)
+        let entries = analysis
+            .functions
+            .iter()
+            .filter_map(|f| {
+                let name = f.name.strip_prefix(ENTRY_PREFIX)?;
+                let max = f.max?;
+                let max = if f.exact {
+                    Max::Exact(max)
+                } else {
+                    Max::LowerBound(max)
+                };
+                Some((name.to_owned(), max))
+            })
+            .collect::<BTreeMap<_, _>>();
+
+        if entries.is_empty() {
+            warn!("the analysis of the secure image contains no entry functions");
+        }
+
+        Secure { entries }
+    }
+
+    /// Returns the secure stack usage of the entry function `name`
+    pub(crate) fn get(&self, name: &str) -> Option<Max> {
+        self.entries.get(name).copied()
+    }
+}
+
+/// Prints to stderr the secure stack usage of the entry functions of a secure image or, if the
+/// analysis of the secure image is given, the non-secure and secure stack usage of each root of a
+/// non-secure image
+pub(crate) fn report(
+    g: &Graph<Node, Edge>,
+    roots: &[NodeIndex],
+    secure: Option<&Secure>,
+) -> io::Result<()> {
+    let stderr = io::stderr();
+    let mut stderr = stderr.lock();
+
+    if let Some(secure) = secure {
+        let mut roots = roots
+            .iter()
+            .map(|root| {
+                let mut dfs = Dfs::new(g, *root);
+                let mut entries = vec![];
+                while let Some(node) = dfs.next(g) {
+                    entries.extend(secure.get(&g[node].name));
+                }
+                (*root, max_of(entries.into_iter()))
+            })
+            .collect::<Vec<_>>();
+        roots.sort_by_key(|(root, _)| cmp::Reverse(max_value(g[*root].max)));
+
+        writeln!(stderr, "Non-secure Secure Root")?;
+        for (root, secure) in roots {
+            writeln!(
+                stderr,
+                "{} {} {}",
+                bytes(g[root].max),
+                secure
+                    .map(|max| bytes(Some(max)))
+                    .unwrap_or_else(|| "-".to_owned()),
+                rustc_demangle::demangle(&g[root].name)
+            )?;
+        }
+        return Ok(());
+    }
+
+    // the gateway uses no stack; the stack usage of an entry function is the one of its body
+    let mut entries = g
+        .node_weights()
+        .filter_map(|node| {
+            let name = node.name.strip_prefix(ENTRY_PREFIX)?;
+            Some((name, node.max))
+        })
+        .collect::<Vec<_>>();
+
+    if entries.is_empty() {
+        warn!(
+            "no secure entry functions (`{}*`) found; to analyze a non-secure image pass the \
+             analysis of the secure image to `--secure`",
+            ENTRY_PREFIX
+        );
+        return Ok(());
+    }
+
+    entries.sort_by_key(|(_, max)| cmp::Reverse(max_value(*max)));
+
+    writeln!(stderr, "Max Entry")?;
+    for (name, max) in &entries {
+        writeln!(stderr, "{} {}", bytes(*max), name)?;
+    }
+    writeln!(
+        stderr,
+        "secure stack: {} bytes worst case when non-secure code calls an entry function",
+        bytes(max_of(entries.iter().filter_map(|(_, max)| *max)))
+    )?;
+
+    Ok(())
+}
+
+fn bytes(max: Option<Max>) -> String {
+    match max {
+        Some(Max::Exact(n)) => n.to_string(),
+        Some(Max::LowerBound(n)) => format!(">={}", n),
+        None => "?".to_owned(),
+    }
+}
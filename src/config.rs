@@ -0,0 +1,228 @@
+use std::{
+    collections::BTreeMap,
+    env, fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::anyhow;
+use serde::Deserialize;
+
+use crate::{Origin, OutputFormat, Roots};
+
+/// Name of the configuration file that's looked up next to `Cargo.toml`
+pub(crate) const FILE_NAME: &str = "call-stack.toml";
+
+/// Project configuration; the command line flags take precedence over these settings
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub(crate) struct Config {
+    /// Target triple for which the code is compiled
+    pub(crate) target: Option<String>,
+    /// Start points
+    pub(crate) start: Vec<String>,
+    /// Automatic root selection
+    pub(crate) roots: Option<Roots>,
+    /// Select all the functions that match an ambiguous start point
+    pub(crate) all_matches: bool,
+    /// Patterns of the functions to leave out of the analysis
+    pub(crate) exclude: Vec<String>,
+    /// Named stacks, with their roots and budgets
+    pub(crate) stack: BTreeMap<String, Stack>,
+    /// Indirect-call overrides: the functions that the indirect calls made by each function (the
+    /// key) can reach
+    pub(crate) indirect: BTreeMap<String, Vec<String>>,
+    /// Stack usage assumed for the functions that match each pattern
+    pub(crate) assume: BTreeMap<String, Assumption>,
+    /// Table of the functions in ROM
+    pub(crate) rom: Option<PathBuf>,
+    pub(crate) output: Output,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct Stack {
+    /// Size of the stack, in bytes
+    pub(crate) budget: Option<u64>,
+    pub(crate) roots: Vec<String>,
+}
+
+/// Assumed stack usage: a number is the maximum stack usage
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+pub(crate) enum Assumption {
+    Max(u64),
+    Usage(Usage),
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct Usage {
+    pub(crate) local: Option<u64>,
+    pub(crate) max: Option<u64>,
+}
+
+impl Assumption {
+    /// Returns the assumed (local, max) stack usage
+    pub(crate) fn usage(&self) -> (Option<u64>, Option<u64>) {
+        match self {
+            Assumption::Max(max) => (None, Some(*max)),
+            Assumption::Usage(usage) => (usage.local, usage.max),
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct Output {
+    pub(crate) format: Option<OutputFormat>,
+    pub(crate) inlined: bool,
+    pub(crate) edges: Vec<Origin>,
+    pub(crate) baseline: Option<PathBuf>,
+    pub(crate) threshold: Option<u64>,
+}
+
+impl Config {
+    pub(crate) fn load(path: &Path) -> anyhow::Result<Self> {
+        let contents = fs::read_to_string(path).map_err(|e| {
+            anyhow!(
+                "couldn't open configuration file `{}`: {}",
+                path.display(),
+                e
+            )
+        })?;
+
+        let mut config: Config = toml::from_str(&contents)
+            .map_err(|e| anyhow!("failed to parse `{}`: {}", path.display(), e))?;
+
+        // paths are relative to the configuration file
+        if let Some(dir) = path.parent() {
+            for path in config.rom.iter_mut().chain(&mut config.output.baseline) {
+                *path = dir.join(&*path);
+            }
+        }
+
+        Ok(config)
+    }
+
+    /// Looks for a configuration file next to the `Cargo.toml` of the current project
+    pub(crate) fn discover() -> Option<PathBuf> {
+        let cwd = env::current_dir().ok()?;
+
+        let root = cwd
+            .ancestors()
+            .find(|dir| dir.join("Cargo.toml").is_file())?;
+        let path = root.join(FILE_NAME);
+
+        if path.is_file() {
+            Some(path)
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Config;
+    use crate::{Origin, OutputFormat, Roots};
+
+    #[test]
+    fn parse() {
+        let config: Config = toml::from_str(
+            r#"
+target = "thumbv7em-none-eabihf"
+roots = "auto"
+exclude = ["defmt::*"]
+
+[stack.core0]
+budget = 8192
+roots = ["main"]
+
+[indirect]
+"app::dispatch" = ["app::on_rx", "app::on_tx"]
+
+[assume]
+"vendor_*" = 256
+"?" = { local = 0 }
+
+[output]
+format = "top"
+edges = ["llvm-ir", "machine-code"]
+"#,
+        )
+        .unwrap();
+
+        assert_eq!(config.target.as_deref(), Some("thumbv7em-none-eabihf"));
+        assert_eq!(config.roots, Some(Roots::Auto));
+        assert_eq!(config.stack["core0"].budget, Some(8192));
+        assert_eq!(config.indirect["app::dispatch"].len(), 2);
+        assert_eq!(config.assume["vendor_*"].usage(), (None, Some(256)));
+        assert_eq!(config.assume["?"].usage(), (Some(0), None));
+        assert_eq!(config.output.format, Some(OutputFormat::Top));
+        assert_eq!(config.output.edges, [Origin::LlvmIr, Origin::MachineCode]);
+
+        assert!(toml::from_str::<Config>("budget = 1").is_err());
+    }
+
+    #[test]
+    fn merge() {
+        use clap::Parser;
+
+        use crate::Args;
+
+        let mut config: Config = toml::from_str(
+            r#"
+target = "thumbv7em-none-eabihf"
+start = ["main"]
+exclude = ["defmt::*"]
+
+[stack.core0]
+budget = 8192
+roots = ["main", "SysTick"]
+
+[stack.core1]
+budget = 4096
+roots = ["app::core1_main"]
+
+[assume]
+"vendor_*" = { local = 0, max = 256 }
+"?" = 64
+
+[output]
+edges = ["llvm-ir"]
+threshold = 16
+"#,
+        )
+        .unwrap();
+
+        let mut args = Args::parse_from([
+            "cargo-call-stack",
+            "-i",
+            "app.elf",
+            "--target",
+            "thumbv6m-none-eabi",
+            "--exclude",
+            "core::panicking::*",
+            "--stack",
+            "core0@1024=main",
+            "--edges",
+            "machine-code",
+            "--assume",
+            "vendor_*=512",
+        ]);
+        args.merge(&mut config);
+
+        // the command line wins
+        assert_eq!(args.target.as_deref(), Some("thumbv6m-none-eabi"));
+        assert_eq!(args.exclude, ["core::panicking::*"]);
+        assert_eq!(args.edges, [Origin::MachineCode]);
+        assert_eq!(args.stack, ["core0@1024=main"]);
+        assert!(!config.stack.contains_key("core0"));
+        assert!(!config.assume.contains_key("vendor_*"));
+        // the settings that were not given on the command line are taken from the file
+        assert_eq!(args.start, ["main"]);
+        assert_eq!(args.threshold, Some(16));
+        assert!(config.stack.contains_key("core1"));
+        assert!(config.assume.contains_key("?"));
+    }
+}
@@ -0,0 +1,202 @@
+use core::fmt;
+
+use addr2line::{
+    gimli::{self, EndianSlice, RunTimeEndian, SectionId},
+    Context,
+};
+use xmas_elf::{header, ElfFile};
+
+/// Source location information extracted from the DWARF sections of the ELF file
+pub struct DebugInfo<'a> {
+    ctx: Context<EndianSlice<'a, RunTimeEndian>>,
+    dwarf: gimli::Dwarf<EndianSlice<'a, RunTimeEndian>>,
+}
+
+/// Source location: file and line
+#[derive(Clone, Debug, PartialEq)]
+pub struct Loc {
+    pub file: String,
+    pub line: u32,
+}
+
+impl fmt::Display for Loc {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}", self.file, self.line)
+    }
+}
+
+/// The state machine of an `async fn` or `async` block
+#[derive(Clone, Debug, PartialEq)]
+pub struct StateMachine {
+    /// Path of the function that contains the `async` code, e.g. `app::foo`
+    pub path: String,
+    /// Name of the type, e.g. `{async_fn_env#0}`
+    pub name: String,
+    /// Size of the state machine, in bytes
+    pub size: u64,
+}
+
+/// A function that has been inlined into another one
+#[derive(Clone, Debug, PartialEq)]
+pub struct Frame {
+    /// Name of the inlined function, as it appears in the DWARF info
+    pub name: String,
+    /// Location, in the parent function, where the function was inlined
+    pub call_site: Option<Loc>,
+}
+
+impl<'a> DebugInfo<'a> {
+    /// Loads the `.debug_*` sections of `elf`
+    ///
+    /// Returns `None` if the ELF file contains no (usable) DWARF information
+    pub fn load(elf: &ElfFile<'a>) -> Option<Self> {
+        elf.find_section_by_name(".debug_info")?;
+        elf.find_section_by_name(".debug_line")?;
+
+        let endian = match elf.header.pt1.data() {
+            header::Data::BigEndian => RunTimeEndian::Big,
+            _ => RunTimeEndian::Little,
+        };
+
+        let load = || {
+            gimli::Dwarf::load(|id: SectionId| -> Result<_, gimli::Error> {
+                let data = elf
+                    .find_section_by_name(id.name())
+                    .map(|sect| sect.raw_data(elf))
+                    .unwrap_or(&[]);
+
+                Ok(EndianSlice::new(data, endian))
+            })
+        };
+
+        // `Context` doesn't give access to the `Dwarf` it owns
+        let ctx = Context::from_dwarf(load().ok()?).ok()?;
+        let dwarf = load().ok()?;
+        Some(DebugInfo { ctx, dwarf })
+    }
+
+    /// Returns the source location of the instruction at `address`
+    pub fn location(&self, address: u64) -> Option<Loc> {
+        self.ctx.find_location(address).ok()?.and_then(to_loc)
+    }
+
+    /// Returns the chain of inlined functions the instruction at `address` belongs to
+    ///
+    /// The chain is sorted from the outermost to the innermost inlined function. The function that
+    /// contains `address` in the machine code is not part of the chain, so an empty chain means that
+    /// the instruction was not inlined.
+    pub fn inline_stack(&self, address: u64) -> Vec<Frame> {
+        let mut iter = match self.ctx.find_frames(address).skip_all_loads() {
+            Ok(iter) => iter,
+            Err(_) => return vec![],
+        };
+
+        // (name, location) from the innermost to the outermost frame
+        let mut frames = vec![];
+        while let Ok(Some(frame)) = iter.next() {
+            let name = frame
+                .function
+                .and_then(|function| function.raw_name().ok().map(|name| name.into_owned()))
+                .unwrap_or_else(|| "?".to_owned());
+            frames.push((name, frame.location.and_then(to_loc)));
+        }
+
+        // the location of the parent frame is where the child was inlined
+        let mut stack = frames
+            .windows(2)
+            .map(|pair| Frame {
+                name: pair[0].0.clone(),
+                call_site: pair[1].1.clone(),
+            })
+            .collect::<Vec<_>>();
+        stack.reverse();
+        stack
+    }
+
+    /// Returns the state machines of the `async` functions and blocks in the program
+    pub fn state_machines(&self) -> Vec<StateMachine> {
+        let mut machines = vec![];
+
+        let mut headers = self.dwarf.units();
+        while let Ok(Some(header)) = headers.next() {
+            let unit = if let Ok(unit) = self.dwarf.unit(header) {
+                unit
+            } else {
+                continue;
+            };
+
+            // (depth, name) of the enclosing namespaces
+            let mut namespaces: Vec<(isize, String)> = vec![];
+            let mut depth = 0;
+            let mut entries = unit.entries();
+            while let Ok(Some((delta, entry))) = entries.next_dfs() {
+                depth += delta;
+                while namespaces.last().map(|(d, _)| *d >= depth) == Some(true) {
+                    namespaces.pop();
+                }
+
+                let name = entry
+                    .attr_value(gimli::DW_AT_name)
+                    .ok()
+                    .flatten()
+                    .and_then(|value| self.dwarf.attr_string(&unit, value).ok())
+                    .map(|name| name.to_string_lossy().into_owned());
+                let name = if let Some(name) = name {
+                    name
+                } else {
+                    continue;
+                };
+
+                match entry.tag() {
+                    gimli::DW_TAG_namespace => namespaces.push((depth, name)),
+
+                    gimli::DW_TAG_structure_type if is_state_machine(&name) => {
+                        let size = entry
+                            .attr_value(gimli::DW_AT_byte_size)
+                            .ok()
+                            .flatten()
+                            .and_then(|value| value.udata_value());
+
+                        if let Some(size) = size {
+                            let path = namespaces
+                                .iter()
+                                .map(|(_, name)| name.as_str())
+                                .collect::<Vec<_>>()
+                                .join("::");
+                            let machine = StateMachine { path, name, size };
+
+                            // the same type can appear in several compilation units
+                            if !machines.contains(&machine) {
+                                machines.push(machine);
+                            }
+                        }
+                    }
+
+                    _ => {}
+                }
+            }
+        }
+
+        machines
+    }
+}
+
+// the compiler names the types of `async` state machines `{async_fn_env#0}`, `{async_block_env#0}`
+// or, in older versions, `{generator#0}` and `{coroutine#0}`
+fn is_state_machine(name: &str) -> bool {
+    [
+        "{async_fn_env#",
+        "{async_block_env#",
+        "{generator#",
+        "{coroutine#",
+    ]
+    .iter()
+    .any(|prefix| name.starts_with(prefix))
+}
+
+fn to_loc(loc: addr2line::Location) -> Option<Loc> {
+    Some(Loc {
+        file: loc.file?.to_owned(),
+        line: loc.line?,
+    })
+}
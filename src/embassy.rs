@@ -0,0 +1,214 @@
+use core::cmp;
+use std::{
+    collections::HashSet,
+    io::{self, Write},
+};
+
+use log::warn;
+use petgraph::{
+    graph::{Graph, NodeIndex},
+    Direction,
+};
+
+use crate::{dehash, ir, max_of, max_value, reach, start::Pattern, Edge, Max, Node};
+
+// the executor functions that poll the tasks, through the `poll_fn` pointer in the `TaskHeader`;
+// the closure that does the call may be inlined into the run queue
+const EXECUTOR: &[&str] = &[
+    "embassy_executor::raw::SyncExecutor::poll*",
+    "embassy_executor::raw::run_queue*::RunQueue::dequeue_all*",
+];
+
+// `poll_fn` always points to the `poll` function of a `TaskStorage<F>`, where `F` is the future
+// returned by the task, or to a function of the executor, like `poll_exited`
+const TASK: &str = "*embassy_executor::raw::TaskStorage*::poll";
+const POLL_FN: &[&str] = &[TASK, "embassy_executor::raw::*poll_exited"];
+
+/// The functions of the Embassy executor and the `poll` functions of the spawned tasks
+pub(crate) struct Embassy {
+    executor: Vec<Pattern>,
+    task: Pattern,
+    poll_fn: Vec<Pattern>,
+    /// The functions whose address is stored in the `poll_fn` field of a `TaskHeader`; `None` if
+    /// none could be found
+    installed: Option<HashSet<String>>,
+}
+
+impl Embassy {
+    /// Finds the `poll` function that each task installs in its `TaskHeader`
+    ///
+    /// The address of the `poll` function of a `TaskStorage<F>` is stored in its `TaskHeader`,
+    /// either in the initial value of the storage or when the task is spawned, so the executor can
+    /// only call the `poll` functions whose address is taken
+    pub(crate) fn new(module: &ir::Module) -> Self {
+        let patterns = |patterns: &[&str]| {
+            patterns
+                .iter()
+                .map(|pattern| Pattern::new(pattern).expect("UNREACHABLE"))
+                .collect::<Vec<_>>()
+        };
+
+        let mut embassy = Embassy {
+            executor: patterns(EXECUTOR),
+            task: Pattern::new(TASK).expect("UNREACHABLE"),
+            poll_fn: patterns(POLL_FN),
+            installed: None,
+        };
+
+        let installed = module
+            .defines
+            .iter()
+            .flat_map(|f| &f.refs)
+            .chain(&module.initializers)
+            .filter(|name| embassy.poll_fn.iter().any(|pattern| pattern.matches(name)))
+            .cloned()
+            .collect::<HashSet<_>>();
+        if installed.iter().any(|name| embassy.task.matches(name)) {
+            embassy.installed = Some(installed);
+        } else {
+            warn!(
+                "couldn't find where the tasks store their `poll` function; assuming that the \
+                 executor can poll every `TaskStorage<F>`"
+            );
+        }
+
+        embassy
+    }
+
+    fn installed(&self, name: &str) -> bool {
+        self.installed
+            .as_ref()
+            .map(|installed| installed.contains(name))
+            .unwrap_or(true)
+    }
+
+    /// Whether this function calls the `poll` function of a task through a function pointer
+    pub(crate) fn is_executor(&self, name: &str) -> bool {
+        self.executor.iter().any(|pattern| pattern.matches(name))
+    }
+
+    /// Whether this is the `poll` function of a spawned task (`TaskStorage<F>::poll`)
+    pub(crate) fn is_task(&self, name: &str) -> bool {
+        self.task.matches(name) && self.installed(name)
+    }
+
+    /// Whether the executor can call this function through the `poll_fn` pointer
+    pub(crate) fn is_poll_fn(&self, name: &str) -> bool {
+        self.poll_fn.iter().any(|pattern| pattern.matches(name)) && self.installed(name)
+    }
+}
+
+/// Prints to stderr the maximum stack usage of each task and how much stack the executor uses
+/// when it polls a task
+pub(crate) fn report(g: &Graph<Node, Edge>, embassy: &Embassy) -> io::Result<()> {
+    let stderr = io::stderr();
+    let mut stderr = stderr.lock();
+
+    let tasks = g
+        .node_indices()
+        .filter(|idx| embassy.is_task(&g[*idx].name))
+        .collect::<Vec<_>>();
+
+    let depths = reach::depths(g, &tasks);
+    let executor = max_of(
+        g.externals(Direction::Incoming)
+            .filter(|idx| !g[*idx].dashed)
+            .filter_map(|idx| depths[idx.index()]),
+    )
+    .unwrap_or(Max::Exact(0));
+
+    let mut tasks = tasks
+        .into_iter()
+        .map(|task| {
+            let poll = g[task].max.unwrap_or(Max::LowerBound(0));
+            (executor + poll, poll, name(g, task))
+        })
+        .collect::<Vec<_>>();
+    tasks.sort_by_key(|(total, _, _)| cmp::Reverse(max_value(Some(*total))));
+
+    writeln!(stderr, "Total Poll Task")?;
+    for (total, poll, name) in tasks {
+        writeln!(stderr, "{} {} {}", bytes(total), bytes(poll), name)?;
+    }
+    writeln!(
+        stderr,
+        "executor: {} bytes in use when a task is polled",
+        bytes(executor)
+    )?;
+
+    Ok(())
+}
+
+fn bytes(max: Max) -> String {
+    match max {
+        Max::Exact(n) => n.to_string(),
+        Max::LowerBound(n) => format!(">={}", n),
+    }
+}
+
+// the name of the task, derived from the type of its future
+fn name(g: &Graph<Node, Edge>, task: NodeIndex) -> String {
+    let demangled = rustc_demangle::demangle(&g[task].name).to_string();
+
+    // with v0 mangling the type of the future is part of the name, e.g.
+    // `<embassy_executor::raw::TaskStorage<app::__blink_task::{closure#0}>>::poll`
+    let future = demangled
+        .find("TaskStorage<")
+        .map(|start| &demangled[start + "TaskStorage<".len()..])
+        .and_then(|rest| rest.rfind(">>::poll").map(|end| rest[..end].to_owned()))
+        .filter(|future| future.contains("::"))
+        // with legacy mangling it's only `TaskStorage<F>`; use the `poll` function of the future,
+        // unless it has been inlined
+        .or_else(|| {
+            g.neighbors_directed(task, Direction::Outgoing)
+                .map(|callee| rustc_demangle::demangle(&g[callee].name).to_string())
+                .find(|callee| {
+                    !callee.starts_with("embassy_")
+                        && !callee.starts_with("core::")
+                        && !callee.ends_with('*')
+                })
+        });
+
+    if let Some(future) = future {
+        task_name(&future)
+    } else {
+        demangled
+    }
+}
+
+// `app::__blink_task::{{closure}}` -> `app::blink`
+fn task_name(future: &str) -> String {
+    let path = dehash(future).unwrap_or(future);
+    let path = path
+        .strip_suffix("::{{closure}}")
+        .or_else(|| path.strip_suffix("::{closure#0}"))
+        .unwrap_or(path);
+    let (parent, last) = path.rsplit_once("::").unwrap_or(("", path));
+    // the `#[task]` macro renames the function to `__{name}_task`
+    let last = last
+        .strip_prefix("__")
+        .and_then(|last| last.strip_suffix("_task"))
+        .unwrap_or(last);
+
+    if parent.is_empty() {
+        last.to_owned()
+    } else {
+        format!("{}::{}", parent, last)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn task_name() {
+        assert_eq!(
+            super::task_name("app::__blink_task::{{closure}}::h0123456789abcdef"),
+            "app::blink"
+        );
+        assert_eq!(
+            super::task_name("app::__blink_task::{closure#0}"),
+            "app::blink"
+        );
+        assert_eq!(super::task_name("app::Blinky"), "app::Blinky");
+    }
+}
@@ -0,0 +1,93 @@
+use core::cmp;
+use std::io::{self, Write};
+
+use petgraph::graph::Graph;
+
+use crate::{dehash, dwarf::StateMachine, max_of, Edge, Max, Node};
+
+/// Prints the size of each `async` state machine next to the maximum stack usage of the function
+/// that polls it, largest state machine first
+pub(crate) fn report(g: &Graph<Node, Edge>, machines: &[StateMachine]) -> io::Result<()> {
+    let stdout = io::stdout();
+    let mut stdout = stdout.lock();
+
+    // (path, index) of the functions that resume a state machine; `None` if the index is not
+    // part of the symbol name (legacy mangling)
+    let resumes = g
+        .node_indices()
+        .filter_map(|idx| {
+            let demangled = rustc_demangle::demangle(&g[idx].name).to_string();
+            let name = dehash(&demangled).unwrap_or(&demangled);
+            let (path, index) = closure(name)?;
+            Some((path.to_owned(), index, idx))
+        })
+        .collect::<Vec<_>>();
+
+    let mut machines = machines.iter().collect::<Vec<_>>();
+    machines.sort_by_key(|machine| cmp::Reverse(machine.size));
+
+    writeln!(stdout, "Size Max Future")?;
+    for machine in machines {
+        let index = machine
+            .name
+            .rsplit_once('#')
+            .and_then(|(_, index)| index.strip_suffix('}'))
+            .and_then(|index| index.parse::<u32>().ok());
+
+        // if several functions could resume this state machine, report the worst case
+        let max = max_of(
+            resumes
+                .iter()
+                .filter(|(path, i, _)| {
+                    *path == machine.path && (i.is_none() || index.is_none() || *i == index)
+                })
+                .filter_map(|(_, _, idx)| g[*idx].max),
+        );
+
+        let max = match max {
+            Some(Max::Exact(n)) => n.to_string(),
+            Some(Max::LowerBound(n)) => format!(">={}", n),
+            // the function was inlined into its caller or is not part of the call graph
+            None => "?".to_owned(),
+        };
+
+        writeln!(
+            stdout,
+            "{} {} {}::{}",
+            machine.size, max, machine.path, machine.name
+        )?;
+    }
+
+    Ok(())
+}
+
+// `app::foo::{closure#1}` -> `("app::foo", Some(1))`; `app::foo::{{closure}}` -> `("app::foo", None)`
+fn closure(name: &str) -> Option<(&str, Option<u32>)> {
+    if let Some(path) = name.strip_suffix("::{{closure}}") {
+        return Some((path, None));
+    }
+
+    let (path, last) = name.rsplit_once("::")?;
+    let (kind, index) = last.strip_prefix('{')?.strip_suffix('}')?.split_once('#')?;
+    if !["closure", "async_fn", "async_block", "coroutine"].contains(&kind) {
+        return None;
+    }
+
+    Some((path, Some(index.parse().ok()?)))
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn closure() {
+        assert_eq!(
+            super::closure("app::foo::{{closure}}"),
+            Some(("app::foo", None))
+        );
+        assert_eq!(
+            super::closure("app::foo::{closure#1}"),
+            Some(("app::foo", Some(1)))
+        );
+        assert_eq!(super::closure("app::foo"), None);
+    }
+}
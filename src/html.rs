@@ -0,0 +1,290 @@
+use core::fmt::Write as _;
+use std::io::{self, Write};
+
+use petgraph::{
+    graph::{Graph, NodeIndex},
+    Direction,
+};
+
+use crate::{max_value, roots, Edge, Local, Max, Node};
+
+pub(crate) fn html(
+    g: &Graph<Node, Edge>,
+    cycles: &[Vec<NodeIndex>],
+    excluded: &[String],
+) -> io::Result<()> {
+    let stdout = io::stdout();
+    let mut stdout = stdout.lock();
+
+    let mut scc = vec![None; g.node_count()];
+    for (i, cycle) in cycles.iter().enumerate() {
+        for node in cycle {
+            scc[node.index()] = Some(i);
+        }
+    }
+
+    // the report is driven by a JSON blob embedded in the page
+    let mut nodes = String::new();
+    for idx in g.node_indices() {
+        let node = &g[idx];
+
+        let (max, exact) = match node.max {
+            Some(Max::Exact(n)) => (n.to_string(), true),
+            Some(Max::LowerBound(n)) => (n.to_string(), false),
+            None => ("null".to_owned(), false),
+        };
+        let local = match node.local {
+            Local::Exact(n) => n.to_string(),
+            Local::Unknown => "null".to_owned(),
+        };
+
+        let callees = g
+            .neighbors_directed(idx, Direction::Outgoing)
+            .map(|n| n.index().to_string())
+            .collect::<Vec<_>>();
+        let callers = g
+            .neighbors_directed(idx, Direction::Incoming)
+            .map(|n| n.index().to_string())
+            .collect::<Vec<_>>();
+
+        writeln!(
+            nodes,
+            "{{\"name\":{},\"loc\":{},\"local\":{},\"max\":{},\"exact\":{},\"indirect\":{},\
+             \"assumed\":{},\"scc\":{},\"callees\":[{}],\"callers\":[{}]}},",
+            json_str(&rustc_demangle::demangle(&node.name).to_string()),
+            node.loc
+                .as_ref()
+                .map(|loc| json_str(&loc.to_string()))
+                .unwrap_or_else(|| "null".to_owned()),
+            local,
+            max,
+            exact,
+            node.dashed,
+            node.assumed,
+            scc[idx.index()]
+                .map(|i| i.to_string())
+                .unwrap_or_else(|| "null".to_owned()),
+            callees.join(","),
+            callers.join(","),
+        )
+        .ok();
+    }
+
+    let roots = roots(g);
+    let worst = worst_path(g, &roots);
+
+    writeln!(stdout, "{}", HEAD)?;
+    writeln!(stdout, "<script>")?;
+    writeln!(
+        stdout,
+        "const EXCLUDED = [{}];",
+        excluded
+            .iter()
+            .map(|pattern| json_str(pattern))
+            .collect::<Vec<_>>()
+            .join(",")
+    )?;
+    writeln!(
+        stdout,
+        "const NODES = [{}];",
+        nodes.trim_end_matches(&[',', '\n'][..])
+    )?;
+    writeln!(
+        stdout,
+        "const ROOTS = [{}];",
+        roots
+            .iter()
+            .map(|n| n.index().to_string())
+            .collect::<Vec<_>>()
+            .join(",")
+    )?;
+    writeln!(
+        stdout,
+        "const WORST = [{}];",
+        worst
+            .iter()
+            .map(|n| n.index().to_string())
+            .collect::<Vec<_>>()
+            .join(",")
+    )?;
+    writeln!(stdout, "{}", SCRIPT)?;
+    writeln!(stdout, "</script>\n</body>\n</html>")
+}
+
+// path that starts at the root with the largest stack usage and always follows the callee with
+// the largest stack usage
+fn worst_path(g: &Graph<Node, Edge>, roots: &[NodeIndex]) -> Vec<NodeIndex> {
+    let mut path = vec![];
+
+    let mut next = roots
+        .iter()
+        .copied()
+        .max_by_key(|idx| max_value(g[*idx].max));
+    while let Some(idx) = next {
+        if path.contains(&idx) {
+            // cycle
+            break;
+        }
+        path.push(idx);
+
+        next = g
+            .neighbors_directed(idx, Direction::Outgoing)
+            .max_by_key(|callee| max_value(g[*callee].max));
+    }
+
+    path
+}
+
+// NOTE `<` is escaped so the string can't close the `<script>` element
+fn json_str(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '<' => out.push_str("\\u003c"),
+            c if (c as u32) < 0x20 => {
+                write!(out, "\\u{:04x}", c as u32).ok();
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+const HEAD: &str = r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>cargo-call-stack report</title>
+<style>
+body { font-family: monospace; margin: 1em; }
+table { border-collapse: collapse; }
+th, td { padding: 2px 8px; text-align: left; }
+th { cursor: pointer; border-bottom: 1px solid #888; }
+tr:hover { background: #eee; }
+td.num { text-align: right; }
+.worst { background: #fdd; }
+.badge { border-radius: 3px; padding: 0 4px; margin-left: 4px; font-size: 80%; color: #fff; }
+.scc { background: #a50; }
+.indirect { background: #06a; }
+.lower { background: #888; }
+.assumed { background: #707; }
+#excluded { background: #ffd; border: 1px solid #cc8; padding: 4px; }
+details { margin-left: 1.5em; }
+summary { cursor: pointer; }
+#panes { display: flex; gap: 2em; }
+#panes > div { flex: 1; overflow: auto; max-height: 90vh; }
+</style>
+</head>
+<body>
+<p id="excluded" hidden></p>
+<div id="panes">
+<div>
+<input id="search" type="search" placeholder="filter functions" size="40">
+<table>
+<thead><tr><th data-key="name">Function</th><th data-key="local">Local</th><th data-key="max">Max</th></tr></thead>
+<tbody id="functions"></tbody>
+</table>
+</div>
+<div>
+<label><input id="callers" type="checkbox"> show callers instead of callees</label>
+<div id="tree"></div>
+</div>
+</div>"#;
+
+const SCRIPT: &str = r#"const worst = new Set(WORST);
+
+if (EXCLUDED.length > 0) {
+  const el = document.getElementById('excluded');
+  el.textContent = `These results assume that the excluded functions are never called: ${EXCLUDED.join(', ')}`;
+  el.hidden = false;
+}
+
+function badges(n) {
+  let html = '';
+  if (n.max !== null && !n.exact) html += '<span class="badge lower" title="lower bound">&ge;</span>';
+  if (n.scc !== null) html += `<span class="badge scc">SCC${n.scc}</span>`;
+  if (n.indirect) html += '<span class="badge indirect">indirect</span>';
+  if (n.assumed) html += '<span class="badge assumed" title="stack usage given by the user">assumed</span>';
+  return html;
+}
+
+function esc(s) {
+  return s.replace(/&/g, '&amp;').replace(/</g, '&lt;').replace(/>/g, '&gt;');
+}
+
+function fmt(v) {
+  return v === null ? '?' : v;
+}
+
+function label(i) {
+  const n = NODES[i];
+  const max = n.max === null ? '' : ` max ${n.exact ? '=' : '>='} ${n.max}`;
+  const loc = n.loc === null ? '' : ` (${esc(n.loc)})`;
+  return `${esc(n.name)}${loc} local = ${fmt(n.local)}${max}${badges(n)}`;
+}
+
+function item(i, seen) {
+  const n = NODES[i];
+  const next = document.getElementById('callers').checked ? n.callers : n.callees;
+  const cls = worst.has(i) ? ' class="worst"' : '';
+  if (next.length === 0 || seen.has(i)) {
+    const el = document.createElement('div');
+    el.style.marginLeft = '1.5em';
+    el.innerHTML = `<span${cls}>${label(i)}${seen.has(i) ? ' (recursion)' : ''}</span>`;
+    return el;
+  }
+  const el = document.createElement('details');
+  el.innerHTML = `<summary${cls}>${label(i)}</summary>`;
+  el.addEventListener('toggle', () => {
+    if (!el.open || el.dataset.loaded) return;
+    el.dataset.loaded = true;
+    const inner = new Set(seen).add(i);
+    next.slice()
+      .sort((a, b) => (NODES[b].max || 0) - (NODES[a].max || 0))
+      .forEach(c => el.appendChild(item(c, inner)));
+  });
+  return el;
+}
+
+let current = ROOTS;
+function tree(roots) {
+  current = roots;
+  const el = document.getElementById('tree');
+  el.innerHTML = '';
+  roots.forEach(r => el.appendChild(item(r, new Set())));
+}
+
+let sortKey = 'max';
+function table() {
+  const query = document.getElementById('search').value.toLowerCase();
+  const rows = NODES.map((n, i) => i)
+    .filter(i => NODES[i].name.toLowerCase().includes(query))
+    .sort((a, b) => sortKey === 'name'
+      ? NODES[a].name.localeCompare(NODES[b].name)
+      : (NODES[b][sortKey] || 0) - (NODES[a][sortKey] || 0));
+  document.getElementById('functions').innerHTML = rows.map(i => {
+    const n = NODES[i];
+    const cls = worst.has(i) ? ' class="worst"' : '';
+    const max = n.max === null ? '?' : `${n.exact ? '' : '&ge; '}${n.max}`;
+    return `<tr${cls} data-id="${i}"><td>${esc(n.name)}${badges(n)}</td>` +
+      `<td class="num">${fmt(n.local)}</td><td class="num">${max}</td></tr>`;
+  }).join('');
+}
+
+document.getElementById('search').addEventListener('input', table);
+document.getElementById('callers').addEventListener('change', () => tree(current));
+document.querySelectorAll('th').forEach(th => th.addEventListener('click', () => {
+  sortKey = th.dataset.key;
+  table();
+}));
+document.getElementById('functions').addEventListener('click', e => {
+  const tr = e.target.closest('tr');
+  if (tr) tree([Number(tr.dataset.id)]);
+});
+
+table();
+tree(ROOTS);"#;
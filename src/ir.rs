@@ -1,14 +1,21 @@
 use std::ffi::CStr;
+use std::path::Path;
 use std::ptr::null_mut;
 use std::str;
 
 use anyhow::bail;
 use llvm_sys::core::*;
 use llvm_sys::prelude::{LLVMBasicBlockRef, LLVMModuleRef, LLVMTypeRef, LLVMValueRef};
+use llvm_sys::{LLVMAttributeFunctionIndex, LLVMOpcode};
+
+use crate::dwarf::Loc;
 
 pub struct Module {
     pub declares: Vec<DeclaredFunction>,
     pub defines: Vec<Function>,
+    /// Global variables and functions that the initial values of global variables refer to, e.g.
+    /// a function pointer stored in a static
+    pub initializers: Vec<String>,
 }
 
 pub struct DeclaredFunction {
@@ -20,19 +27,29 @@ pub struct Function {
     pub name: String,
     pub sig: String,
     pub callees: Vec<Callee>,
+    /// Global variables and functions that this function uses other than by calling them, e.g. a
+    /// static it accesses or a function whose address it stores
+    pub refs: Vec<String>,
+    /// Constant addresses that this function stores to, e.g. memory-mapped registers
+    pub writes: Vec<u64>,
 }
 
 pub enum Callee {
     Direct(DirectCallee),
     Indirect(IndirectCallee),
+    /// A call from secure code to a non-secure function (ARMv8-M Security Extension)
+    NonSecure(IndirectCallee),
 }
 
 pub struct DirectCallee {
     pub name: String,
+    /// Source location of the call instruction, if the bitcode contains debug info
+    pub loc: Option<Loc>,
 }
 
 pub struct IndirectCallee {
     pub sig: String,
+    pub loc: Option<Loc>,
 }
 
 pub fn parse(bitcode: &[u8]) -> anyhow::Result<Module> {
@@ -52,8 +69,18 @@ pub fn parse(bitcode: &[u8]) -> anyhow::Result<Module> {
         let mut res = Module {
             declares: Vec::new(),
             defines: Vec::new(),
+            initializers: Vec::new(),
         };
 
+        for global in iter_globals(module) {
+            let init = LLVMGetInitializer(global);
+            if !init.is_null() {
+                globals(init, &mut res.initializers);
+            }
+        }
+        res.initializers.sort();
+        res.initializers.dedup();
+
         for f in iter_funcs(module) {
             if LLVMIsDeclaration(f) != 0 {
                 continue;
@@ -63,32 +90,55 @@ pub fn parse(bitcode: &[u8]) -> anyhow::Result<Module> {
                 name: value_name(f),
                 sig: stringify_ty(LLVMGlobalGetValueType(f)),
                 callees: Vec::new(),
+                refs: Vec::new(),
+                writes: Vec::new(),
             };
 
             for bb in iter_basic_blocks(f) {
                 for inst in iter_instructions(bb) {
-                    if LLVMIsACallInst(inst).is_null() {
+                    let is_call = !LLVMIsACallInst(inst).is_null();
+                    // the called value is the last operand of a call instruction
+                    let operands = LLVMGetNumOperands(inst) as u32 - u32::from(is_call);
+                    for i in 0..operands {
+                        globals(LLVMGetOperand(inst, i), &mut ff.refs);
+                    }
+
+                    if !LLVMIsAStoreInst(inst).is_null() {
+                        // the pointer is the second operand of a store instruction
+                        if let Some(address) = address(LLVMGetOperand(inst, 1)) {
+                            ff.writes.push(address);
+                        }
+                    }
+
+                    if !is_call {
                         continue;
                     }
 
                     let callee = LLVMGetCalledValue(inst);
+                    let loc = debug_loc(inst);
                     if !LLVMIsAInlineAsm(callee).is_null() {
                         //println!("inline asm");
                     } else if !LLVMIsAConstant(callee).is_null() {
                         // direct call
                         let name = value_name(callee);
                         if !name.starts_with("llvm.") {
-                            ff.callees.push(Callee::Direct(DirectCallee { name }))
+                            ff.callees.push(Callee::Direct(DirectCallee { name, loc }))
                         }
                     } else {
                         // indirect call
                         let ty = stringify_ty(LLVMGetCalledFunctionType(inst));
-                        ff.callees
-                            .push(Callee::Indirect(IndirectCallee { sig: ty }))
+                        let callee = IndirectCallee { sig: ty, loc };
+                        if is_nonsecure_call(inst) {
+                            ff.callees.push(Callee::NonSecure(callee))
+                        } else {
+                            ff.callees.push(Callee::Indirect(callee))
+                        }
                     }
                 }
             }
 
+            ff.refs.sort();
+            ff.refs.dedup();
             res.defines.push(ff);
         }
 
@@ -96,6 +146,48 @@ pub fn parse(bitcode: &[u8]) -> anyhow::Result<Module> {
     }
 }
 
+// collects the names of the global variables and functions that `v` refers to, looking through
+// constant expressions like `getelementptr` and aggregates like structs
+unsafe fn globals(v: LLVMValueRef, refs: &mut Vec<String>) {
+    if !LLVMIsAGlobalValue(v).is_null() {
+        let name = value_name(v);
+        if !name.starts_with("llvm.") {
+            refs.push(name);
+        }
+    } else if !LLVMIsAConstant(v).is_null() {
+        for i in 0..LLVMGetNumOperands(v) as u32 {
+            globals(LLVMGetOperand(v, i), refs);
+        }
+    }
+}
+
+// the value of a pointer that's an integer constant, e.g. `inttoptr (i32 -536813056 to ptr)`
+unsafe fn address(v: LLVMValueRef) -> Option<u64> {
+    if LLVMIsAConstantExpr(v).is_null() || LLVMGetConstOpcode(v) != LLVMOpcode::LLVMIntToPtr {
+        return None;
+    }
+
+    let int = LLVMGetOperand(v, 0);
+    if LLVMIsAConstantInt(int).is_null() {
+        None
+    } else {
+        Some(LLVMConstIntGetZExtValue(int))
+    }
+}
+
+// calls through a `extern "C-cmse-nonsecure-call"` function pointer
+unsafe fn is_nonsecure_call(inst: LLVMValueRef) -> bool {
+    const KIND: &str = "cmse_nonsecure_call";
+
+    !LLVMGetCallSiteStringAttribute(
+        inst,
+        LLVMAttributeFunctionIndex,
+        KIND.as_ptr() as _,
+        KIND.len() as _,
+    )
+    .is_null()
+}
+
 unsafe fn stringify(v: LLVMValueRef) -> String {
     CStr::from_ptr(LLVMPrintValueToString(v))
         .to_str()
@@ -109,6 +201,32 @@ unsafe fn stringify_ty(v: LLVMTypeRef) -> String {
         .to_owned()
 }
 
+unsafe fn debug_loc(inst: LLVMValueRef) -> Option<Loc> {
+    let line = LLVMGetDebugLocLine(inst);
+    if line == 0 {
+        return None;
+    }
+
+    let mut len = 0;
+    let p = LLVMGetDebugLocFilename(inst, &mut len);
+    if p.is_null() {
+        return None;
+    }
+    let file = str::from_utf8(std::slice::from_raw_parts(p as *const u8, len as usize)).ok()?;
+
+    // make the path absolute, like the paths in the DWARF info of the ELF file
+    let mut len = 0;
+    let p = LLVMGetDebugLocDirectory(inst, &mut len);
+    let file = if p.is_null() || Path::new(file).is_absolute() {
+        file.to_owned()
+    } else {
+        let dir = str::from_utf8(std::slice::from_raw_parts(p as *const u8, len as usize)).ok()?;
+        Path::new(dir).join(file).display().to_string()
+    };
+
+    Some(Loc { file, line })
+}
+
 unsafe fn value_name(v: LLVMValueRef) -> String {
     let mut len = 0;
     let mut p = LLVMGetValueName2(v, &mut len);
@@ -128,6 +246,19 @@ unsafe fn iter_funcs(m: LLVMModuleRef) -> impl Iterator<Item = LLVMValueRef> {
     })
 }
 
+unsafe fn iter_globals(m: LLVMModuleRef) -> impl Iterator<Item = LLVMValueRef> {
+    let mut g = LLVMGetFirstGlobal(m);
+    std::iter::from_fn(move || {
+        if g.is_null() {
+            None
+        } else {
+            let g2 = g;
+            g = LLVMGetNextGlobal(g2);
+            Some(g2)
+        }
+    })
+}
+
 unsafe fn iter_basic_blocks(m: LLVMValueRef) -> impl Iterator<Item = LLVMBasicBlockRef> {
     let mut f = LLVMGetFirstBasicBlock(m);
     std::iter::from_fn(move || {
@@ -1,7 +1,8 @@
 use core::{
     cmp,
     fmt::{self, Write as _},
-    ops, str,
+    hash::Hash,
+    mem, ops, str,
 };
 use std::{
     borrow::Cow,
@@ -22,20 +23,54 @@ use log::{error, warn};
 use petgraph::{
     algo,
     graph::{DiGraph, NodeIndex},
-    visit::{Dfs, Reversed, Topo},
+    visit::{Dfs, EdgeRef, Reversed, Topo},
     Direction, Graph,
 };
-use xmas_elf::{sections::SectionData, symbol_table::Entry, ElfFile};
+use serde::Deserialize;
+use xmas_elf::{
+    sections::{SectionData, ShType, SHF_EXECINSTR},
+    symbol_table::Entry,
+    ElfFile,
+};
 
-use crate::thumb::Tag;
+use crate::{
+    baseline::Analysis,
+    config::Config,
+    dwarf::{DebugInfo, Frame, Loc},
+    start::Pattern,
+    thumb::Tag,
+};
 
+mod baseline;
+mod cmse;
+mod config;
+mod dwarf;
+mod embassy;
+mod futures;
+mod html;
 mod ir;
+mod reach;
+mod rom;
+mod rtic;
+mod stacks;
+mod start;
 mod thumb;
+mod veneer;
+mod xml;
 
-#[derive(ValueEnum, PartialEq, Debug, Clone, Copy)]
+#[derive(Deserialize, ValueEnum, PartialEq, Debug, Clone, Copy)]
+#[serde(rename_all = "kebab-case")]
 enum OutputFormat {
     Dot,
     Top,
+    Html,
+    Folded,
+    Callgrind,
+    Graphml,
+    Gexf,
+    Json,
+    Panics,
+    Futures,
 }
 
 /// Generate a call graph and perform whole program stack usage analysis
@@ -46,6 +81,10 @@ struct Args {
     #[clap(short)]
     input: PathBuf,
 
+    /// Configuration file [default: `call-stack.toml` next to `Cargo.toml`, if it exists]
+    #[arg(long, value_name = "PATH")]
+    config: Option<PathBuf>,
+
     /// Target triple for which the code is compiled
     #[arg(long, value_name = "TRIPLE")]
     target: Option<String>,
@@ -54,12 +93,145 @@ struct Args {
     #[arg(short, long)]
     verbose: bool,
 
-    /// Output format
-    #[arg(long, default_value = "dot")]
-    format: OutputFormat,
+    /// Output format [default: dot]
+    #[arg(long)]
+    format: Option<OutputFormat>,
+
+    /// Compare the analysis against a baseline saved with `--format json` and report the changes
+    #[arg(long, value_name = "PATH")]
+    baseline: Option<PathBuf>,
+
+    /// Increase, in bytes, of a function's maximum stack usage above which `--baseline` reports a
+    /// regression [default: 0]
+    #[arg(long, value_name = "BYTES")]
+    threshold: Option<u64>,
+
+    /// Only keep call edges with these origins
+    #[arg(long, value_name = "ORIGIN", value_delimiter = ',')]
+    edges: Vec<Origin>,
+
+    /// Render functions inlined at call sites as zero-cost nodes (requires debug info)
+    #[arg(long)]
+    inlined: bool,
+
+    /// Select the roots of the call graph automatically: functions that are never called and, on
+    /// ARM Cortex-M, the exception and interrupt handlers in the vector table
+    #[arg(long, value_name = "MODE")]
+    roots: Option<Roots>,
+
+    /// Select all the functions that match an ambiguous start point
+    #[arg(long)]
+    all_matches: bool,
+
+    /// Leave out of the analysis the functions that match this pattern, and the functions that
+    /// only they call
+    #[arg(long, value_name = "PATTERN")]
+    exclude: Vec<String>,
+
+    /// Table of the functions in ROM that the program can call: one `START[-END] NAME [MAX]` entry
+    /// per line
+    #[arg(long, value_name = "PATH")]
+    rom: Option<PathBuf>,
+
+    /// Assume that the functions that match a pattern use at most this much stack, including their
+    /// callees: `PATTERN=BYTES`. Use `?` for the node that represents unknown functions
+    #[arg(long, value_name = "ASSUMPTION")]
+    assume: Vec<String>,
+
+    /// Only keep the functions that can reach the functions that match this pattern and report
+    /// how much stack is in use when they are reached
+    #[arg(long, value_name = "PATTERN")]
+    to: Option<String>,
+
+    /// Analyze the program as an RTIC application and report the worst-case stack usage of the
+    /// whole system, taking task preemption into account (ARM Cortex-M only)
+    #[arg(long)]
+    rtic: bool,
+
+    /// File that lists the priority of each RTIC task, one `PRIORITY HANDLER` pair per line; takes
+    /// precedence over the priorities derived from the symbols that RTIC generates
+    #[arg(long, value_name = "PATH", requires = "rtic")]
+    priorities: Option<PathBuf>,
+
+    /// Resolve the calls that the Embassy executor makes to the `poll` functions of the tasks and
+    /// report the maximum stack usage of each task
+    #[arg(long)]
+    embassy: bool,
+
+    /// Analyze an ARMv8-M image that uses the Security Extension (TrustZone) and report the secure
+    /// stack usage of its entry functions or, with `--secure`, of the calls into the secure image
+    #[arg(long)]
+    cmse: bool,
+
+    /// Analysis of the secure image, saved with `--format json`, that the non-secure image being
+    /// analyzed calls into
+    #[arg(long, value_name = "PATH", requires = "cmse")]
+    secure: Option<PathBuf>,
+
+    /// Check a stack on its own: `NAME[@BUDGET]=PATTERN` adds the functions that match the pattern
+    /// to the roots of stack `NAME`, whose size is `BUDGET` bytes; can be given several times
+    #[arg(long, value_name = "STACK")]
+    stack: Vec<String>,
+
+    /// consider only the call graph that starts from these nodes; glob patterns (`*`, `?`) and
+    /// regular expressions (`/regex/`) match demangled names
+    start: Vec<String>,
+}
+
+#[derive(Deserialize, ValueEnum, PartialEq, Debug, Clone, Copy)]
+#[serde(rename_all = "kebab-case")]
+enum Roots {
+    Auto,
+}
+
+impl Args {
+    // fills in the settings that were not given on the command line. A setting given on the
+    // command line overrides the configuration file: a list (start points, exclusions, edge
+    // origins) replaces the list in the file, a stack replaces the stack with the same name and an
+    // assumption replaces the assumption for the same pattern. Flags can only be turned on
+    fn merge(&mut self, config: &mut Config) {
+        if self.target.is_none() {
+            self.target = config.target.take();
+        }
+        if self.start.is_empty() {
+            self.start = mem::take(&mut config.start);
+        }
+        if self.roots.is_none() {
+            self.roots = config.roots;
+        }
+        self.all_matches |= config.all_matches;
+        if self.exclude.is_empty() {
+            self.exclude = mem::take(&mut config.exclude);
+        }
+        let stacks = &self.stack;
+        config
+            .stack
+            .retain(|name, _| !stacks.iter().any(|arg| stacks::name(arg) == name));
+        let assume = &self.assume;
+        config.assume.retain(|pattern, _| {
+            !assume
+                .iter()
+                .any(|arg| arg.rsplit_once('=').map(|(pattern, _)| pattern) == Some(pattern))
+        });
 
-    /// consider only the call graph that starts from this node
-    start: Option<String>,
+        let output = &mut config.output;
+        if self.format.is_none() {
+            self.format = output.format;
+        }
+        self.inlined |= output.inlined;
+        if self.edges.is_empty() {
+            self.edges = mem::take(&mut output.edges);
+        }
+        if self.baseline.is_none() {
+            self.baseline = output.baseline.take();
+        }
+        if self.threshold.is_none() {
+            self.threshold = output.threshold;
+        }
+        if self.rom.is_none() {
+            self.rom = config.rom.take();
+        }
+    }
 }
 
 fn main() -> anyhow::Result<()> {
@@ -79,7 +251,90 @@ const FONT: &str = "monospace";
 fn run() -> anyhow::Result<i32> {
     Builder::from_env(Env::default().default_filter_or("warn")).init();
 
-    let args = Args::parse();
+    let mut args = Args::parse();
+
+    let mut config = if let Some(path) = args.config.clone().or_else(Config::discover) {
+        Config::load(&path)?
+    } else {
+        Config::default()
+    };
+    args.merge(&mut config);
+
+    let baseline = if let Some(path) = &args.baseline {
+        let json = fs::read(path)
+            .map_err(|e| anyhow!("couldn't open baseline `{}`: {}", path.display(), e))?;
+        let analysis: Analysis = serde_json::from_slice(&json)
+            .map_err(|e| anyhow!("failed to parse baseline `{}`: {}", path.display(), e))?;
+        Some(analysis)
+    } else {
+        None
+    };
+
+    let secure = if let Some(path) = &args.secure {
+        let json =
+            fs::read(path).map_err(|e| anyhow!("couldn't open `{}`: {}", path.display(), e))?;
+        let analysis: Analysis = serde_json::from_slice(&json)
+            .map_err(|e| anyhow!("failed to parse `{}`: {}", path.display(), e))?;
+        Some(cmse::Secure::new(&analysis))
+    } else {
+        None
+    };
+
+    let starts = args
+        .start
+        .iter()
+        .map(|start| Pattern::new(start))
+        .collect::<Result<Vec<_>, _>>()?;
+    let excludes = args
+        .exclude
+        .iter()
+        .map(|exclude| Pattern::new(exclude))
+        .collect::<Result<Vec<_>, _>>()?;
+    let to = args.to.as_deref().map(Pattern::new).transpose()?;
+    let mut stacks = stacks::parse(&args.stack)?;
+    for (name, stack) in &config.stack {
+        for root in &stack.roots {
+            stacks::add(&mut stacks, name, stack.budget, Pattern::new(root)?)?;
+        }
+    }
+    let overrides = config
+        .indirect
+        .iter()
+        .map(|(caller, callees)| {
+            Ok((
+                Pattern::new(caller)?,
+                callees
+                    .iter()
+                    .map(|callee| Pattern::new(callee))
+                    .collect::<Result<Vec<_>, _>>()?,
+            ))
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    // (pattern, local, max); the assumptions given on the command line come last so they win
+    let mut assumptions = vec![];
+    for (pattern, assumption) in &config.assume {
+        let (local, max) = assumption.usage();
+        assumptions.push((Pattern::new(pattern)?, local, max));
+    }
+    for arg in &args.assume {
+        let (pattern, max) = arg
+            .rsplit_once('=')
+            .ok_or_else(|| anyhow!("invalid assumption `{}`; expected `PATTERN=BYTES`", arg))?;
+        let max = max
+            .parse::<u64>()
+            .map_err(|e| anyhow!("invalid assumption `{}`: {}", arg, e))?;
+        assumptions.push((Pattern::new(pattern)?, None, Some(max)));
+    }
+    let priorities = args
+        .priorities
+        .as_deref()
+        .map(rtic::Priorities::load)
+        .transpose()?;
+    // the results of the analysis are conditional on these exclusions; they are listed in the output
+    let excluded = excludes
+        .iter()
+        .map(|exclude| exclude.to_string())
+        .collect::<Vec<_>>();
 
     let elf_bytes = fs::read(&args.input)
         .map_err(|e| anyhow!("couldn't open ELF file `{}`: {}", args.input.display(), e))?;
@@ -99,19 +354,46 @@ fn run() -> anyhow::Result<i32> {
 
     let ir = ir::parse(&ir)?;
 
+    // source locations are optional; without DWARF info the graph is just less annotated
+    let debug_info = DebugInfo::load(&elf);
+    if debug_info.is_none() {
+        warn!("ELF file contains no debug info; source locations will not be reported");
+    }
+
     let mut defines: HashMap<_, _> = ir.defines.iter().map(|f| (f.name.as_str(), f)).collect();
     let mut declares: HashMap<_, _> = ir.declares.iter().map(|f| (f.name.as_str(), f)).collect();
+    let embassy = if args.embassy {
+        Some(embassy::Embassy::new(&ir))
+    } else {
+        None
+    };
 
-    let target = args.target.as_ref().unwrap().as_str();
+    let target = args.target.as_deref().ok_or_else(|| {
+        anyhow!(
+            "the target must be given with `--target` or in `{}`",
+            config::FILE_NAME
+        )
+    })?;
 
     // we know how to analyze the machine code in the ELF file for these targets thus we have more
     // information and need less LLVM-IR hacks
     let target_ = match target {
         "thumbv6m-none-eabi" => Target::Thumbv6m,
         "thumbv7m-none-eabi" | "thumbv7em-none-eabi" | "thumbv7em-none-eabihf" => Target::Thumbv7m,
+        "thumbv8m.base-none-eabi" | "thumbv8m.main-none-eabi" | "thumbv8m.main-none-eabihf" => {
+            Target::Thumbv8m
+        }
         _ => Target::Other,
     };
 
+    // functions that are called but not defined in the ELF file, like the ones in mask ROM
+    let mut rom = if let Some(path) = &args.rom {
+        rom::Rom::load(path)?
+    } else {
+        rom::Rom::default()
+    };
+    rom.add_absolute_symbols(&elf, target_.is_thumb());
+
     // extract stack size information
     // extract list of "live" symbols (symbols that have not been GC-ed by the linker)
     // this time we use the ELF and not the object file
@@ -147,7 +429,7 @@ fn run() -> anyhow::Result<i32> {
         })
         .collect();
 
-    let mut g = DiGraph::<Node, ()>::new();
+    let mut g = DiGraph::<Node, Edge>::new();
     let mut indices = BTreeMap::<Cow<str>, _>::new();
 
     let mut indirects: HashMap<String, Indirect> = HashMap::new();
@@ -245,6 +527,11 @@ fn run() -> anyhow::Result<i32> {
         let idx = g.add_node(Node(canonical_name, stack, false));
         indices.insert(canonical_name.into(), idx);
 
+        g[idx].address = Some(*address);
+        g[idx].loc = debug_info
+            .as_ref()
+            .and_then(|debug_info| debug_info.location(*address));
+
         if let Some(def) = names.iter().filter_map(|name| defines.get(name)).next() {
             indirects
                 .entry(def.sig.clone())
@@ -257,9 +544,17 @@ fn run() -> anyhow::Result<i32> {
             .next()
         {
             indirects.entry(sig).or_default().callees.insert(idx);
-        } else if !is_outlined_function(canonical_name) {
+        } else if !is_outlined_function(canonical_name)
+            && !veneer::is_veneer(canonical_name)
+            && !cmse::is_entry(canonical_name)
+            && secure
+                .as_ref()
+                .map_or(true, |secure| secure.get(canonical_name).is_none())
+        {
             // ^ functions produced by LLVM's function outliner are never called through function
-            // pointers (as of LLVM 14.0.6)
+            // pointers (as of LLVM 14.0.6), and neither are the veneers generated by the linker nor
+            // the secure entry functions, which are called through their gateways; the gateways
+            // of the secure image are called from non-secure code
             has_untyped_symbols = true;
             warn!("no type information for `{}`", canonical_name);
         }
@@ -269,10 +564,13 @@ fn run() -> anyhow::Result<i32> {
     let mut fns_containing_asm: HashSet<&str> = HashSet::new();
     let mut llvm_seen = HashSet::new();
     // add edges
-    let mut edges: HashMap<_, HashSet<_>> = HashMap::new(); // NodeIdx -> [NodeIdx]
     let mut defined = HashSet::new(); // functions that are `define`-d in the LLVM-IR
     for define in defines.values() {
-        let canonical_name = match aliases.get(define.name.as_str()) {
+        // the body of a secure entry function is `__acle_se_{name}`; `{name}` is its gateway
+        let canonical_name = match aliases
+            .get(cmse::entry(&define.name).as_str())
+            .or_else(|| aliases.get(define.name.as_str()))
+        {
             Some(canonical_name) => canonical_name,
             None => {
                 // this symbol was GC-ed by the linker, skip
@@ -281,7 +579,6 @@ fn run() -> anyhow::Result<i32> {
         };
         defined.insert(*canonical_name);
         let caller = indices[*canonical_name];
-        let callees_seen = edges.entry(caller).or_default();
 
         for stmt in &define.callees {
             match stmt {
@@ -314,8 +611,8 @@ fn run() -> anyhow::Result<i32> {
                     g.add_edge(caller, callee, ());
                 }
                 */
-                Callee::Direct(callee) => {
-                    let func = callee.name.as_str();
+                Callee::Direct(callee_) => {
+                    let func = callee_.name.as_str();
                     match func {
                         // no-op / debug-info
                         "llvm.dbg.value" => continue,
@@ -338,10 +635,13 @@ fn run() -> anyhow::Result<i32> {
                     }
 
                     let mut call = |callee| {
-                        if !callees_seen.contains(&callee) {
-                            g.add_edge(caller, callee, ());
-                            callees_seen.insert(callee);
-                        }
+                        add_call(
+                            &mut g,
+                            caller,
+                            callee,
+                            Origin::Intrinsic,
+                            Some(CallSite::ir(callee_.loc.clone())),
+                        );
                     };
 
                     if target_.is_thumb() && func.starts_with("llvm.") {
@@ -449,6 +749,8 @@ fn run() -> anyhow::Result<i32> {
                     // use canonical name
                     let callee = if let Some(canon) = aliases.get(func) {
                         indices[*canon]
+                    } else if let Some(entry) = rom.get(func) {
+                        entry.node(&mut g, &mut indices)
                     } else {
                         assert!(
                             symbols.undefined.contains(func),
@@ -466,19 +768,36 @@ fn run() -> anyhow::Result<i32> {
                         }
                     };
 
-                    if !callees_seen.contains(&callee) {
-                        callees_seen.insert(callee);
-                        g.add_edge(caller, callee, ());
-                    }
+                    add_call(
+                        &mut g,
+                        caller,
+                        callee,
+                        Origin::LlvmIr,
+                        Some(CallSite::ir(callee_.loc.clone())),
+                    );
                 }
                 Callee::Indirect(callee) => {
                     for (key_sig, indirect) in &mut indirects {
                         if key_sig == &callee.sig {
                             indirect.called = true;
-                            indirect.callers.insert(caller);
+                            indirect
+                                .callers
+                                .entry(caller)
+                                .or_default()
+                                .push(CallSite::ir(callee.loc.clone()));
                         }
                     }
                 }
+                Callee::NonSecure(callee) => {
+                    let ns = cmse::non_secure(&mut g, &mut indices);
+                    add_call(
+                        &mut g,
+                        caller,
+                        ns,
+                        Origin::LlvmIr,
+                        Some(CallSite::ir(callee.loc.clone())),
+                    );
+                }
             }
         }
     }
@@ -510,38 +829,119 @@ fn run() -> anyhow::Result<i32> {
 
         tags.sort_by(|a, b| a.0.cmp(&b.0));
 
-        if let Some(sect) = elf.find_section_by_name(".text") {
-            let stext = sect.address() as u32;
-            let text = sect.raw_data(&elf);
+        // `.text` plus any other section that contains code, like the sections that the linker
+        // script places in RAM or in a distant flash bank
+        let code = elf
+            .section_iter()
+            .filter(|sect| {
+                sect.get_type() == Ok(ShType::ProgBits) && sect.flags() & SHF_EXECINSTR != 0
+            })
+            .map(|sect| (sect.address() as u32, sect.raw_data(&elf)))
+            .collect::<Vec<_>>();
+
+        if !code.is_empty() {
+            // the address range of each function, used to resolve branches that don't target the
+            // start of a symbol
+            let functions = Functions {
+                code: code
+                    .iter()
+                    .map(|(start, bytes)| u64::from(*start)..u64::from(*start) + bytes.len() as u64)
+                    .collect(),
+                ranges: symbols
+                    .defined
+                    .iter()
+                    .map(|(address, sym)| {
+                        let name = aliases[&sym.names()[0]];
+                        let mut end = *address
+                            + u64::from(symbol_size(*address as u32, sym.size() as u32, &tags));
+                        if end == *address && cmse::is_entry(name) {
+                            // the linker gives the size of a secure entry function to its
+                            // gateway; the function extends up to the next symbol
+                            let section = code.iter().find(|(start, bytes)| {
+                                u64::from(*start) <= *address
+                                    && *address < u64::from(*start) + bytes.len() as u64
+                            });
+                            if let Some((start, bytes)) = section {
+                                let section_end = u64::from(*start) + bytes.len() as u64;
+                                end = symbols
+                                    .defined
+                                    .range(*address + 1..)
+                                    .next()
+                                    .map(|(next, _)| cmp::min(*next, section_end))
+                                    .unwrap_or(section_end);
+                            }
+                        }
+                        (*address, (end, name))
+                    })
+                    .collect(),
+            };
 
             for (address, sym) in &symbols.defined {
                 let address = *address as u32;
                 let canonical_name = aliases[&sym.names()[0]];
-                let mut size = sym.size() as u32;
-
-                if size == 0 {
-                    // try harder at finding out the size of this symbol
-                    if let Ok(needle) = tags.binary_search_by(|tag| tag.0.cmp(&address)) {
-                        let start = tags[needle];
-                        if start.1 == Tag::Thumb {
-                            if let Some(end) = tags.get(needle + 1) {
-                                if end.1 == Tag::Thumb {
-                                    size = end.0 - start.0;
-                                }
-                            }
-                        }
+                let size = (functions.ranges[&u64::from(address)].0 - u64::from(address)) as u32;
+
+                let bytes = if let Some((section, bytes)) = code.iter().find(|(start, bytes)| {
+                    *start <= address && address + size <= *start + bytes.len() as u32
+                }) {
+                    let start = (address - section) as usize;
+                    &bytes[start..start + size as usize]
+                } else {
+                    // functions in ROM, or in the secure image, are expected to be outside the
+                    // code sections
+                    if rom.find(u64::from(address)).is_none() {
+                        warn!(
+                            "`{}` is not in a code section; its machine code will not be analyzed",
+                            canonical_name
+                        );
+                    }
+                    continue;
+                };
+                let caller = indices[canonical_name];
+
+                if veneer::is_veneer(canonical_name) {
+                    // veneers don't use the stack; they are pass-through nodes between the
+                    // caller and the function they jump to
+                    let callee = if let Some(target) = veneer::target(bytes, address) {
+                        Some(functions.node(
+                            u64::from(target),
+                            canonical_name,
+                            &rom,
+                            &mut g,
+                            &mut indices,
+                        ))
+                    } else {
+                        veneer::target_name(canonical_name).and_then(|name| {
+                            indices.get(*aliases.get(name).unwrap_or(&name)).copied()
+                        })
+                    };
+
+                    if let Some(callee) = callee {
+                        g[caller].local = Local::Exact(0);
+                        add_call(
+                            &mut g,
+                            caller,
+                            callee,
+                            Origin::MachineCode,
+                            Some(CallSite::at(
+                                u64::from(address),
+                                Some(Some(0)),
+                                debug_info.as_ref(),
+                            )),
+                        );
+                        continue;
                     }
+
+                    warn!(
+                        "couldn't find out where the veneer `{}` jumps to",
+                        canonical_name
+                    );
                 }
 
-                let start = (address - stext) as usize;
-                let end = start + size as usize;
-                let (bls, bs, indirect, modifies_sp, our_stack) = thumb::analyze(
-                    &text[start..end],
-                    address,
-                    target_ == Target::Thumbv7m,
-                    &tags,
-                );
-                let caller = indices[canonical_name];
+                // ARMv8-M Mainline is a superset of ARMv7-M; the ARMv7-M instructions that
+                // ARMv8-M Baseline lacks never appear in its machine code
+                let (bls, bs, indirect, modifies_sp, our_stack, nonsecure) =
+                    thumb::analyze(bytes, address, target_ != Target::Thumbv6m, &tags);
 
                 // sanity check
                 if let Some(stack) = our_stack {
@@ -571,6 +971,17 @@ fn run() -> anyhow::Result<i32> {
                                 canonical_name, llvm_stack, stack
                             );
 
+                            *llvm_stack = stack;
+                        } else if *llvm_stack < stack && nonsecure {
+                            // LLVM's stack usage analysis doesn't include the space that secure
+                            // code reserves to save its registers before calling non-secure code
+                            warn!(
+                                "LLVM reported that `{}` uses {} bytes of stack but \
+                                 our analysis reported {} bytes; overriding LLVM's result (function \
+                                 calls non-secure code)",
+                                canonical_name, llvm_stack, stack
+                            );
+
                             *llvm_stack = stack;
                         } else if is_outlined_function(canonical_name) {
                             // ^ functions produced by LLVM's function outliner are not properly
@@ -638,45 +1049,55 @@ fn run() -> anyhow::Result<i32> {
                         canonical_name,
                     );
                     let callee = g.add_node(Node("?", None, false));
-                    g.add_edge(caller, callee, ());
+                    g.add_edge(caller, callee, Edge::new(Origin::MachineCode));
+                }
+
+                if nonsecure {
+                    // secure code that calls non-secure code (`BLXNS`); the callee runs on the
+                    // non-secure stack
+                    let callee = cmse::non_secure(&mut g, &mut indices);
+                    add_call(&mut g, caller, callee, Origin::MachineCode, None);
                 }
 
-                let callees_seen = edges.entry(caller).or_default();
-                for offset in bls {
+                for (site, offset) in bls {
                     let addr = (address as i64 + i64::from(offset)) as u64;
-                    // address may be off by one due to the thumb bit being set
-                    let name = addr2name
-                        .get(&addr)
-                        .unwrap_or_else(|| panic!("BUG? no symbol at address {}", addr));
-
-                    let callee = indices[*name];
-                    if !callees_seen.contains(&callee) {
-                        g.add_edge(caller, callee, ());
-                        callees_seen.insert(callee);
-                    }
+                    let callee = functions.node(addr, canonical_name, &rom, &mut g, &mut indices);
+                    let site = (address as i64 + i64::from(site)) as u64;
+                    add_call(
+                        &mut g,
+                        caller,
+                        callee,
+                        Origin::MachineCode,
+                        Some(CallSite::at(site, None, debug_info.as_ref())),
+                    );
                 }
 
-                for offset in bs {
-                    let addr = (address as i32 + offset) as u32;
+                for (site, offset, frame) in bs {
+                    let addr = (i64::from(address) + i64::from(offset)) as u32;
 
                     if addr >= address && addr < (address + size) {
                         // intra-function B branches are not function calls
                     } else {
-                        // address may be off by one due to the thumb bit being set
-                        let name = addr2name
-                            .get(&(addr as u64))
-                            .unwrap_or_else(|| panic!("BUG? no symbol at address {}", addr));
-
-                        let callee = indices[*name];
-                        if !callees_seen.contains(&callee) {
-                            g.add_edge(caller, callee, ());
-                            callees_seen.insert(callee);
-                        }
+                        let callee = functions.node(
+                            u64::from(addr),
+                            canonical_name,
+                            &rom,
+                            &mut g,
+                            &mut indices,
+                        );
+                        let site = (i64::from(address) + i64::from(site)) as u64;
+                        add_call(
+                            &mut g,
+                            caller,
+                            callee,
+                            Origin::MachineCode,
+                            Some(CallSite::at(site, Some(frame), debug_info.as_ref())),
+                        );
                     }
                 }
             }
         } else {
-            error!(".text section not found")
+            error!("the ELF file contains no code sections")
         }
     }
 
@@ -688,6 +1109,9 @@ fn run() -> anyhow::Result<i32> {
         );
     }
 
+    // the callers that have an override -> whether the override covers any of their indirect calls,
+    // and the signatures of the calls it doesn't cover
+    let mut overridden = BTreeMap::<NodeIndex, (bool, Vec<String>)>::new();
     for (mut sig, indirect) in indirects {
         if !indirect.called {
             continue;
@@ -699,16 +1123,96 @@ fn run() -> anyhow::Result<i32> {
         // append '*' to denote that this is a function pointer
         name.push('*');
 
+        let mut callers = indirect.callers;
+        // the functions that these callers can reach were given in the configuration file
+        callers.retain(|caller, sites| {
+            let patterns = if let Some((_, patterns)) = overrides
+                .iter()
+                .find(|(pattern, _)| pattern.matches(&g[*caller].name))
+            {
+                patterns
+            } else {
+                return true;
+            };
+
+            let targets = callees
+                .iter()
+                .copied()
+                .filter(|callee| {
+                    patterns
+                        .iter()
+                        .any(|pattern| pattern.matches(&g[*callee].name))
+                })
+                .collect::<Vec<_>>();
+            let (applied, uncovered) = overridden.entry(*caller).or_default();
+            if targets.is_empty() {
+                uncovered.push(sig.to_string());
+                return true;
+            }
+            *applied = true;
+
+            for callee in targets {
+                g.add_edge(
+                    *caller,
+                    callee,
+                    Edge {
+                        origin: Origin::Signature,
+                        sites: sites.clone(),
+                    },
+                );
+            }
+            false
+        });
+
+        if let Some(embassy) = embassy
+            .as_ref()
+            .filter(|embassy| callees.iter().any(|idx| embassy.is_task(&g[*idx].name)))
+        {
+            // the executor polls the tasks through this signature; connect it directly to the
+            // functions it can call instead of every function with this signature
+            callers.retain(|caller, sites| {
+                if !embassy.is_executor(&g[*caller].name) {
+                    return true;
+                }
+
+                for callee in callees {
+                    if embassy.is_poll_fn(&g[*callee].name) {
+                        g.add_edge(
+                            *caller,
+                            *callee,
+                            Edge {
+                                origin: Origin::Signature,
+                                sites: sites.clone(),
+                            },
+                        );
+                    }
+                }
+                false
+            });
+        }
+
+        // all the calls made through this signature have been resolved
+        if callers.is_empty() {
+            continue;
+        }
+
         let call = g.add_node(Node(name.clone(), Some(0), true));
 
-        for caller in &indirect.callers {
-            g.add_edge(*caller, call, ());
+        for (caller, sites) in callers {
+            g.add_edge(
+                caller,
+                call,
+                Edge {
+                    origin: Origin::Signature,
+                    sites,
+                },
+            );
         }
 
         if has_untyped_symbols {
             // add an edge between this and a potential extern / untyped symbol
             let extern_sym = g.add_node(Node("?", None, false));
-            g.add_edge(call, extern_sym, ());
+            g.add_edge(call, extern_sym, Edge::new(Origin::Signature));
         } else {
             if callees.is_empty() {
                 error!("BUG? no callees for `{}`", name);
@@ -716,45 +1220,184 @@ fn run() -> anyhow::Result<i32> {
         }
 
         for callee in callees {
-            g.add_edge(call, *callee, ());
+            g.add_edge(call, *callee, Edge::new(Origin::Signature));
+        }
+    }
+
+    // an override that covers one of the indirect calls of its caller is applied to that call and
+    // the other calls are handled as if there was no override
+    for (caller, (applied, uncovered)) in overridden {
+        if !applied {
+            warn!(
+                "none of the indirect callees listed for `{}` has the signature of its indirect \
+                 calls (`{}`); ignoring the override",
+                g[caller].name,
+                uncovered.join("`, `")
+            );
+        }
+    }
+
+    // stack usage given by the user, e.g. for functions linked in from binary blobs
+    for (pattern, local, max) in &assumptions {
+        let nodes = g
+            .node_indices()
+            .filter(|idx| pattern.matches(&g[*idx].name))
+            .collect::<Vec<_>>();
+        if nodes.is_empty() {
+            warn!("no function matches `{}`; ignoring the assumption", pattern);
+        }
+
+        for idx in nodes {
+            let node = &mut g[idx];
+            if let Some(local) = local {
+                node.local = Local::Exact(*local);
+            }
+            if max.is_some() {
+                node.max_assumed = *max;
+            }
+            node.assumed = true;
+        }
+    }
+
+    // the secure entry functions run on the secure stack; they use no non-secure stack
+    if let Some(secure) = &secure {
+        let entries = g
+            .node_indices()
+            .filter(|idx| secure.get(&g[*idx].name).is_some())
+            .collect::<Vec<_>>();
+        if entries.is_empty() {
+            warn!("the program doesn't call any of the entry functions of the secure image");
+        }
+
+        for idx in entries {
+            g[idx].local = Local::Exact(0);
+        }
+    }
+
+    if !args.edges.is_empty() {
+        g.retain_edges(|g, edge| args.edges.contains(&g[edge].origin));
+    }
+
+    // calls made from inlined code are attributed to the function that contains the machine code;
+    // insert the inlined functions as zero-cost nodes between the caller and the callee so that the
+    // graph matches the call structure of the source code
+    if args.inlined {
+        // (caller, callee, origin, call site) for every call site that's in inlined code
+        let mut calls = vec![];
+        // edges that only have call sites in inlined code
+        let mut moved = HashSet::new();
+        for edge in g.edge_indices() {
+            let (caller, callee) = g.edge_endpoints(edge).expect("UNREACHABLE");
+            let origin = g[edge].origin;
+            let (inlined, direct) = mem::take(&mut g[edge].sites)
+                .into_iter()
+                .partition::<Vec<_>, _>(|site| !site.inlined.is_empty());
+            if !inlined.is_empty() && direct.is_empty() {
+                moved.insert(edge);
+            }
+            calls.extend(
+                inlined
+                    .into_iter()
+                    .map(|site| (caller, callee, origin, site)),
+            );
+            g[edge].sites = direct;
+        }
+        g.retain_edges(|_, edge| !moved.contains(&edge));
+
+        // (caller, inline stack) -> virtual node
+        let mut virtuals = HashMap::<(NodeIndex, Vec<String>), NodeIndex>::new();
+        for (caller, callee, origin, mut site) in calls {
+            let stack = mem::take(&mut site.inlined);
+
+            let mut parent = caller;
+            let mut path = vec![];
+            for frame in stack {
+                path.push(frame.name.clone());
+
+                let node = *virtuals.entry((caller, path.clone())).or_insert_with(|| {
+                    let node = g.add_node(Node(frame.name.clone(), Some(0), false));
+                    g[node].inlined = true;
+                    node
+                });
+                add_call(
+                    &mut g,
+                    parent,
+                    node,
+                    origin,
+                    Some(CallSite {
+                        address: site.address,
+                        loc: frame.call_site,
+                        inlined: vec![],
+                        tail: false,
+                        frame: None,
+                    }),
+                );
+
+                parent = node;
+            }
+
+            add_call(&mut g, parent, callee, origin, Some(site));
         }
     }
 
     // filter the call graph
-    if let Some(start) = &args.start {
-        let start: &str = start;
-        let start = indices.get(start).cloned().or_else(|| {
-            let start_ = start.to_owned() + "::h";
-            let hits = indices
-                .keys()
-                .filter_map(|key| {
-                    if rustc_demangle::demangle(key)
-                        .to_string()
-                        .starts_with(&start_)
-                    {
-                        Some(key)
-                    } else {
-                        None
-                    }
-                })
-                .collect::<Vec<_>>();
+    let mut starts = starts
+        .iter()
+        .flat_map(|start| start::resolve(start, &g, &indices, args.all_matches))
+        .collect::<Vec<_>>();
+
+    if args.roots == Some(Roots::Auto) {
+        starts.extend(
+            g.externals(Direction::Incoming)
+                .filter(|idx| !g[*idx].dashed && !g[*idx].inlined),
+        );
 
-            if hits.len() > 1 {
-                error!("multiple matches for `{}`: {:?}", start, hits);
-                None
-            } else {
-                hits.first().map(|key| indices[*key])
+        if target_.is_thumb() {
+            for address in start::vector_table(&elf) {
+                if let Some(name) = addr2name.get(&address) {
+                    starts.push(indices[*name]);
+                } else {
+                    warn!(
+                        "no symbol at address {:#x}, listed in the vector table",
+                        address
+                    );
+                }
             }
-        });
+        }
+    }
+
+    // the roots of each stack
+    let mut stack_roots = stacks
+        .iter()
+        .map(|stack| {
+            let mut roots = stack
+                .roots
+                .iter()
+                .flat_map(|root| start::resolve(root, &g, &indices, args.all_matches))
+                .collect::<Vec<_>>();
+            // a root can match several patterns; it only runs once
+            roots.sort();
+            roots.dedup();
+            roots
+        })
+        .collect::<Vec<_>>();
+
+    starts.sort();
+    starts.dedup();
 
-        if let Some(start) = start {
-            // create a new graph that only contains nodes reachable from `start`
-            let mut g2 = DiGraph::<Node, ()>::new();
+    // the start points, as indices into the filtered graph
+    let mut roots = vec![];
+    if !starts.is_empty() {
+        // create a new graph that only contains nodes reachable from the start points
+        let mut g2 = DiGraph::<Node, Edge>::new();
 
-            // maps `g`'s `NodeIndex`-es to `g2`'s `NodeIndex`-es
-            let mut one2two = BTreeMap::new();
+        // maps `g`'s `NodeIndex`-es to `g2`'s `NodeIndex`-es
+        let mut one2two = BTreeMap::new();
 
-            let mut dfs = Dfs::new(&g, start);
+        // the roots of the stacks are kept as well, even if no start point reaches them
+        let mut dfs = Dfs::empty(&g);
+        for start in starts.iter().chain(stack_roots.iter().flatten()) {
+            dfs.move_to(*start);
             while let Some(caller1) = dfs.next(&g) {
                 let caller2 = if let Some(i2) = one2two.get(&caller1) {
                     *i2
@@ -765,7 +1408,7 @@ fn run() -> anyhow::Result<i32> {
                 };
 
                 let mut callees = g.neighbors(caller1).detach();
-                while let Some((_, callee1)) = callees.next(&g) {
+                while let Some((edge1, callee1)) = callees.next(&g) {
                     let callee2 = if let Some(i2) = one2two.get(&callee1) {
                         *i2
                     } else {
@@ -774,17 +1417,109 @@ fn run() -> anyhow::Result<i32> {
                         i2
                     };
 
-                    g2.add_edge(caller2, callee2, ());
+                    g2.add_edge(caller2, callee2, g[edge1].clone());
                 }
             }
+        }
+
+        roots = starts.iter().map(|start| one2two[start]).collect();
+        remap_stacks(&g, &stacks, &mut stack_roots, &one2two, "not reachable");
+
+        // replace the old graph
+        g = g2;
+
+        // invalidate `indices` to prevent misuse
+        indices.clear();
+    } else if !args.start.is_empty() || args.roots.is_some() {
+        error!("no start point found; the graph will not be filtered")
+    }
+
+    // remove the excluded functions, and the functions that are only reachable through them
+    if !excludes.is_empty() {
+        let matching = g
+            .node_indices()
+            .filter(|idx| {
+                excludes
+                    .iter()
+                    .any(|exclude| exclude.matches(&g[*idx].name))
+            })
+            .collect::<HashSet<_>>();
+        let matches = matching.len();
+
+        let entries = roots
+            .iter()
+            .chain(stack_roots.iter().flatten())
+            .copied()
+            .collect::<Vec<_>>();
+        let removed = reach::excluded(&g, &matching, &entries);
+
+        if matches == 0 {
+            warn!("no function matches the exclusion patterns");
+        } else {
+            warn!(
+                "excluding {} function(s) that match `{}` plus {} function(s) that are only \
+                 reachable through them; the results assume that these functions are never called",
+                matches,
+                excluded.join("`, `"),
+                removed.len() - matches,
+            );
+        }
 
-            // replace the old graph
+        let (g2, one2two) = subgraph(&g, |idx| !removed.contains(&idx));
+        remap_stacks(&g, &stacks, &mut stack_roots, &one2two, "excluded");
+        g = g2;
+        roots = remap(&roots, &one2two);
+
+        // invalidate `indices` to prevent misuse
+        indices.clear();
+    }
+
+    // keep only the functions that can reach the target
+    if let Some(to) = &to {
+        let targets = g
+            .node_indices()
+            .filter(|idx| to.matches(&g[*idx].name))
+            .collect::<Vec<_>>();
+
+        if targets.is_empty() {
+            error!("`{}` not found; the graph will not be filtered", to);
+        } else {
+            let ancestors = reach::ancestors(&g, &targets);
+            let (g2, one2two) = subgraph(&g, |idx| ancestors.contains(&idx));
+            remap_stacks(
+                &g,
+                &stacks,
+                &mut stack_roots,
+                &one2two,
+                &format!("can't reach `{}`", to),
+            );
             g = g2;
+            let targets = targets
+                .iter()
+                .map(|target| one2two[target])
+                .collect::<Vec<_>>();
+
+            roots = remap(&roots, &one2two);
 
             // invalidate `indices` to prevent misuse
             indices.clear();
-        } else {
-            error!("start point not found; the graph will not be filtered")
+
+            let depths = reach::depths(&g, &targets);
+            let mut sources = if roots.is_empty() {
+                g.externals(Direction::Incoming)
+                    .filter(|idx| !g[*idx].dashed && !g[*idx].inlined)
+                    .collect::<Vec<_>>()
+            } else {
+                roots.clone()
+            };
+            if sources.is_empty() {
+                // all the callers are part of cycles; report all of them
+                sources = g
+                    .node_indices()
+                    .filter(|idx| !targets.contains(idx))
+                    .collect();
+            }
+            reach::report(&g, &sources, &depths)?;
         }
     }
 
@@ -829,7 +1564,9 @@ fn run() -> anyhow::Result<i32> {
 
                 for inode in scc {
                     let node = &mut g[*inode];
-                    if let Some(max) = neighbors_max {
+                    if let Some(max) = node.max_assumed {
+                        node.max = Some(Max::Exact(max));
+                    } else if let Some(max) = neighbors_max {
                         node.max = Some(max + scc_local);
                     } else {
                         node.max = Some(scc_local);
@@ -838,17 +1575,7 @@ fn run() -> anyhow::Result<i32> {
             } else {
                 let inode = first;
 
-                let neighbors_max = max_of(
-                    g.neighbors_directed(inode, Direction::Outgoing)
-                        .map(|neighbor| g[neighbor].max.expect("UNREACHABLE")),
-                );
-
-                let node = &mut g[inode];
-                if let Some(max) = neighbors_max {
-                    node.max = Some(max + node.local);
-                } else {
-                    node.max = Some(node.local.into());
-                }
+                g[inode].max = Some(max_or_assumed(&g, inode));
             }
         }
     } else {
@@ -857,16 +1584,46 @@ fn run() -> anyhow::Result<i32> {
         while let Some(node) = topo.next(Reversed(&g)) {
             debug_assert!(g[node].max.is_none());
 
-            let neighbors_max = max_of(
-                g.neighbors_directed(node, Direction::Outgoing)
-                    .map(|neighbor| g[neighbor].max.expect("UNREACHABLE")),
-            );
+            g[node].max = Some(max_or_assumed(&g, node));
+        }
+    }
+
+    if args.rtic {
+        if target_.is_thumb() {
+            if !rtic::is_rtic(&defines) {
+                bail!(
+                    "no items generated by RTIC (`__rtic_internal_*`) found; `--rtic` supports \
+                     applications built with RTIC v1 or v2"
+                );
+            }
+
+            let handlers = start::vector_table(&elf)
+                .iter()
+                .map(|address| {
+                    let name = addr2name.get(address)?;
+                    g.node_indices().find(|idx| g[*idx].name == **name)
+                })
+                .collect::<Vec<_>>();
+            let (reset, tasks) = rtic::tasks(&g, &handlers, &defines, priorities.as_ref());
+            rtic::report(&g, reset, &tasks, target.ends_with("eabihf"))?;
+        } else {
+            error!("`--rtic` is only supported on ARM Cortex-M targets");
+        }
+    }
 
-            if let Some(max) = neighbors_max {
-                g[node].max = Some(max + g[node].local);
+    if let Some(embassy) = &embassy {
+        embassy::report(&g, embassy)?;
+    }
+
+    if args.cmse {
+        if target_ == Target::Thumbv8m {
+            if roots.is_empty() {
+                cmse::report(&g, &self::roots(&g), secure.as_ref())?;
             } else {
-                g[node].max = Some(g[node].local.into());
+                cmse::report(&g, &roots, secure.as_ref())?;
             }
+        } else {
+            error!("`--cmse` is only supported on ARMv8-M targets");
         }
     }
 
@@ -875,27 +1632,96 @@ fn run() -> anyhow::Result<i32> {
         let demangled = rustc_demangle::demangle(&node.name).to_string();
 
         if let Some(dehashed) = dehash(&demangled) {
-            if ambiguous[dehashed] == 1 {
-                node.name = Cow::Owned(dehashed.to_owned());
+            if ambiguous.get(dehashed) == Some(&1) {
+                node.mangled = Some(mem::replace(
+                    &mut node.name,
+                    Cow::Owned(dehashed.to_owned()),
+                ));
             }
         }
     }
 
-    match args.format {
-        OutputFormat::Dot => dot(g, &cycles)?,
-        OutputFormat::Top => top(g)?,
+    if roots.len() > 1 {
+        start::summary(&g, &roots)?;
+    }
+
+    let over_budget = if stacks.is_empty() {
+        false
+    } else {
+        // on ARM Cortex-M the roots of a stack are the entry point and the exception handlers
+        let frame = if target_.is_thumb() {
+            rtic::exception_frame(target.ends_with("eabihf"))
+        } else {
+            0
+        };
+        stacks::check(&g, &stacks, &stack_roots, frame)?
+    };
+
+    if let Some(baseline) = &baseline {
+        let regressed = baseline::diff(
+            baseline,
+            &Analysis::new(&g, &cycles, &excluded),
+            args.threshold.unwrap_or(0),
+        )?;
+        return Ok(if regressed || over_budget { 1 } else { 0 });
+    }
+
+    match args.format.unwrap_or(OutputFormat::Dot) {
+        OutputFormat::Dot => dot(g, &cycles, &excluded)?,
+        OutputFormat::Top => top(g, &excluded)?,
+        OutputFormat::Html => html::html(&g, &cycles, &excluded)?,
+        OutputFormat::Folded => folded(g, &excluded)?,
+        OutputFormat::Callgrind => callgrind(g, &excluded)?,
+        OutputFormat::Graphml => xml::graphml(&g, &cycles, &excluded)?,
+        OutputFormat::Gexf => xml::gexf(&g, &cycles, &excluded)?,
+        OutputFormat::Json => {
+            let stdout = io::stdout();
+            let mut stdout = stdout.lock();
+            serde_json::to_writer_pretty(&mut stdout, &Analysis::new(&g, &cycles, &excluded))?;
+            writeln!(stdout)?;
+        }
+        OutputFormat::Panics => {
+            let roots = if roots.is_empty() {
+                self::roots(&g)
+            } else {
+                roots
+            };
+
+            if reach::panics(&g, &roots)? {
+                return Ok(1);
+            }
+        }
+        OutputFormat::Futures => {
+            if let Some(debug_info) = &debug_info {
+                futures::report(&g, &debug_info.state_machines())?;
+            } else {
+                bail!("`--format futures` requires debug info");
+            }
+        }
     }
 
-    Ok(0)
+    Ok(if over_budget { 1 } else { 0 })
 }
 
-fn dot(g: Graph<Node, ()>, cycles: &[Vec<NodeIndex>]) -> io::Result<()> {
+fn dot(g: Graph<Node, Edge>, cycles: &[Vec<NodeIndex>], excluded: &[String]) -> io::Result<()> {
     let stdout = io::stdout();
     let mut stdout = stdout.lock();
 
     writeln!(stdout, "digraph {{")?;
     writeln!(stdout, "    node [fontname={} shape=box]", FONT)?;
 
+    if !excluded.is_empty() {
+        write!(
+            stdout,
+            "    labelloc=t\n    fontname={}\n    label=\"",
+            FONT
+        )?;
+        let mut escaper = Escaper::new(&mut stdout);
+        write!(escaper, "excluded: {}", excluded.join(", ")).ok();
+        escaper.error?;
+        writeln!(stdout, "\"")?;
+    }
+
     for (i, node) in g.raw_nodes().iter().enumerate() {
         let node = &node.weight;
 
@@ -903,28 +1729,76 @@ fn dot(g: Graph<Node, ()>, cycles: &[Vec<NodeIndex>]) -> io::Result<()> {
 
         let mut escaper = Escaper::new(&mut stdout);
         write!(escaper, "{}", rustc_demangle::demangle(&node.name)).ok();
+        if let Some(loc) = &node.loc {
+            write!(escaper, "\\n{}", loc).ok();
+        }
         escaper.error?;
 
         if let Some(max) = node.max {
             write!(stdout, "\\nmax {}", max)?;
         }
 
-        write!(stdout, "\\nlocal = {}\"", node.local,)?;
+        write!(stdout, "\\nlocal = {}", node.local,)?;
+        if node.assumed {
+            write!(stdout, "\\n(assumed)")?;
+        }
+        write!(stdout, "\"")?;
 
         if node.dashed {
             write!(stdout, " style=dashed")?;
+        } else if node.inlined {
+            write!(stdout, " style=dotted")?;
         }
 
         writeln!(stdout, "]")?;
     }
 
     for edge in g.raw_edges() {
-        writeln!(
+        write!(
             stdout,
             "    {} -> {}",
             edge.source().index(),
             edge.target().index()
         )?;
+
+        let edge = &edge.weight;
+        match edge.origin {
+            Origin::LlvmIr => {}
+            Origin::Intrinsic => write!(stdout, " [color=blue]")?,
+            Origin::MachineCode => write!(stdout, " [color=darkgreen]")?,
+            Origin::Signature => write!(stdout, " [style=dashed]")?,
+        }
+
+        if edge.is_tail_call() {
+            write!(stdout, " [arrowhead=empty]")?;
+        }
+
+        // call sites without location information (LLVM-IR without debug info) are only counted
+        let known = edge
+            .sites
+            .iter()
+            .filter(|site| site.loc.is_some() || site.address.is_some())
+            .collect::<Vec<_>>();
+        if !known.is_empty() {
+            write!(stdout, " [label=\"")?;
+            let mut escaper = Escaper::new(&mut stdout);
+            for (i, site) in known.iter().enumerate() {
+                if i != 0 {
+                    write!(escaper, "\\n").ok();
+                }
+                write!(escaper, "{}", site).ok();
+                // list the functions that were inlined at the call site, innermost first
+                for frame in site.inlined.iter().rev() {
+                    write!(escaper, "\\n  in {}", rustc_demangle::demangle(&frame.name)).ok();
+                }
+            }
+            escaper.error?;
+            write!(stdout, "\"]")?;
+        } else if edge.sites.len() > 1 {
+            write!(stdout, " [label=\"{} calls\"]", edge.sites.len())?;
+        }
+
+        writeln!(stdout)?;
     }
 
     for (i, cycle) in cycles.iter().enumerate() {
@@ -943,7 +1817,7 @@ fn dot(g: Graph<Node, ()>, cycles: &[Vec<NodeIndex>]) -> io::Result<()> {
     writeln!(stdout, "}}")
 }
 
-pub(crate) fn top(g: Graph<Node, ()>) -> io::Result<()> {
+pub(crate) fn top(g: Graph<Node, Edge>, excluded: &[String]) -> io::Result<()> {
     let stdout = io::stdout();
     let mut stdout = stdout.lock();
 
@@ -966,6 +1840,10 @@ pub(crate) fn top(g: Graph<Node, ()>) -> io::Result<()> {
         )?;
     }
 
+    for pattern in excluded {
+        writeln!(stdout, "Excluded {}", pattern)?;
+    }
+
     writeln!(stdout, "Usage Function")?;
 
     nodes.sort_by(|a, b| {
@@ -984,12 +1862,274 @@ pub(crate) fn top(g: Graph<Node, ()>) -> io::Result<()> {
         write!(stdout, "{} ", val)?;
 
         let mut escaper = Escaper::new(&mut stdout);
-        writeln!(escaper, "{}", name).ok();
+        write!(escaper, "{}", name).ok();
+        if let Some(loc) = &node.loc {
+            write!(escaper, " ({})", loc).ok();
+        }
+        if node.assumed {
+            write!(escaper, " (assumed)").ok();
+        }
+        writeln!(escaper).ok();
         escaper.error?;
     }
+
+    // why the edges in the call graph exist
+    let mut origins = BTreeMap::new();
+    for edge in g.raw_edges() {
+        *origins.entry(edge.weight.origin).or_insert(0) += 1;
+    }
+
+    writeln!(stdout, "Edges Origin")?;
+    for (origin, count) in origins {
+        writeln!(stdout, "{} {}", count, origin)?;
+    }
+
+    Ok(())
+}
+
+// Callgrind format; see https://valgrind.org/docs/manual/cl-format.html
+//
+// Each function's self cost is its local stack usage. Callgrind tools compute the inclusive cost of
+// a function as the *sum* of its self cost and the cost of its calls but the maximum stack usage is
+// the local stack usage plus the *maximum* over all callees. To make the inclusive cost match `max`
+// the call to the callee with the largest stack usage is charged `max - local` and all other calls
+// are charged 0 bytes.
+fn callgrind(g: Graph<Node, Edge>, excluded: &[String]) -> io::Result<()> {
+    // name compression: the first time a file or function is emitted it's assigned a numeric ID
+    fn id<K>(ids: &mut HashMap<K, usize>, key: K, name: &str) -> (usize, Option<String>)
+    where
+        K: Eq + Hash,
+    {
+        let next = ids.len() + 1;
+        if let Some(id) = ids.get(&key) {
+            (*id, None)
+        } else {
+            ids.insert(key, next);
+            (next, Some(name.to_owned()))
+        }
+    }
+
+    fn compressed(key: &str, (id, name): (usize, Option<String>)) -> String {
+        if let Some(name) = name {
+            format!("{}=({}) {}", key, id, name)
+        } else {
+            format!("{}=({})", key, id)
+        }
+    }
+
+    let stdout = io::stdout();
+    let mut stdout = stdout.lock();
+
+    let summary = max_of(g.node_weights().filter_map(|node| node.max))
+        .map(|max| max_value(Some(max)))
+        .unwrap_or(0);
+
+    writeln!(stdout, "# callgrind format")?;
+    writeln!(stdout, "version: 1")?;
+    writeln!(
+        stdout,
+        "creator: cargo-call-stack {}",
+        env!("CARGO_PKG_VERSION")
+    )?;
+    if !excluded.is_empty() {
+        writeln!(stdout, "desc: Excluded: {}", excluded.join(", "))?;
+    }
+    writeln!(stdout, "positions: line")?;
+    writeln!(stdout, "events: Stack")?;
+    writeln!(stdout, "summary: {}", summary)?;
+
+    // functions are identified by their node; the tools that read the profile merge the functions
+    // that have the same name so distinct nodes with the same name, like several `?` nodes, get a
+    // `'N` suffix
+    let mut names = vec![];
+    let mut seen = HashMap::<String, usize>::new();
+    for node in g.node_weights() {
+        let name = rustc_demangle::demangle(&node.name).to_string();
+        let count = seen.entry(name.clone()).or_default();
+        *count += 1;
+        names.push(if *count == 1 {
+            name
+        } else {
+            format!("{}'{}", name, count)
+        });
+    }
+
+    let mut files = HashMap::new();
+    let mut fns = HashMap::new();
+    let file_of = |node: &Node| {
+        node.loc
+            .as_ref()
+            .map(|loc| loc.file.clone())
+            .unwrap_or_else(|| "???".to_owned())
+    };
+    let line_of = |loc: Option<&Loc>| loc.map(|loc| loc.line).unwrap_or(0);
+
+    for caller in g.node_indices() {
+        let node = &g[caller];
+        let local = if let Local::Exact(n) = node.local {
+            n
+        } else {
+            0
+        };
+
+        writeln!(stdout)?;
+        let file = file_of(node);
+        writeln!(
+            stdout,
+            "{}",
+            compressed("fl", id(&mut files, file.clone(), &file))
+        )?;
+        writeln!(
+            stdout,
+            "{}",
+            compressed("fn", id(&mut fns, caller, &names[caller.index()]))
+        )?;
+        writeln!(stdout, "{} {}", line_of(node.loc.as_ref()), local)?;
+
+        let worst = g
+            .edges_directed(caller, Direction::Outgoing)
+            .max_by_key(|edge| max_value(g[edge.target()].max))
+            .map(|edge| edge.id());
+
+        for edge in g.edges_directed(caller, Direction::Outgoing) {
+            let callee_idx = edge.target();
+            let callee = &g[callee_idx];
+            let cost = if Some(edge.id()) == worst {
+                max_value(node.max).saturating_sub(local)
+            } else {
+                0
+            };
+
+            let file = file_of(callee);
+            writeln!(
+                stdout,
+                "{}",
+                compressed("cfi", id(&mut files, file.clone(), &file))
+            )?;
+            writeln!(
+                stdout,
+                "{}",
+                compressed("cfn", id(&mut fns, callee_idx, &names[callee_idx.index()]))
+            )?;
+            let sites = &edge.weight().sites;
+            writeln!(
+                stdout,
+                "calls={} {}",
+                sites.len().max(1),
+                line_of(callee.loc.as_ref())
+            )?;
+            writeln!(
+                stdout,
+                "{} {}",
+                line_of(sites.first().and_then(|site| site.loc.as_ref())),
+                cost
+            )?;
+        }
+    }
+
+    Ok(())
+}
+
+// upper bound on the number of paths `folded` visits, including the paths through functions that
+// use no stack and are not emitted; the number of paths can grow exponentially with the size of the
+// graph
+const MAX_PATHS: usize = 1_000_000;
+
+fn folded(g: Graph<Node, Edge>, excluded: &[String]) -> io::Result<()> {
+    fn walk(
+        g: &Graph<Node, Edge>,
+        node: NodeIndex,
+        path: &mut Vec<NodeIndex>,
+        visited: &mut usize,
+        stdout: &mut impl Write,
+    ) -> io::Result<()> {
+        if *visited >= MAX_PATHS {
+            return Ok(());
+        }
+        *visited += 1;
+
+        path.push(node);
+
+        if let Local::Exact(n) = g[node].local {
+            if n != 0 {
+                for (i, frame) in path.iter().enumerate() {
+                    if i != 0 {
+                        write!(stdout, ";")?;
+                    }
+
+                    // `;` is the frame separator
+                    let name = rustc_demangle::demangle(&g[*frame].name).to_string();
+                    write!(stdout, "{}", name.replace(';', ","))?;
+                }
+                writeln!(stdout, " {}", n)?;
+            }
+        }
+
+        for callee in g.neighbors_directed(node, Direction::Outgoing) {
+            // break cycles
+            if !path.contains(&callee) {
+                walk(g, callee, path, visited, stdout)?;
+            }
+        }
+
+        path.pop();
+
+        Ok(())
+    }
+
+    // the format has no place for metadata so the exclusions go to stderr
+    if !excluded.is_empty() {
+        warn!(
+            "the folded stacks leave out the functions that match `{}`; the results assume that \
+             these functions are never called",
+            excluded.join("`, `")
+        );
+    }
+
+    let stdout = io::stdout();
+    let mut stdout = stdout.lock();
+
+    let mut visited = 0;
+    for root in roots(&g) {
+        walk(&g, root, &mut vec![], &mut visited, &mut stdout)?;
+    }
+
+    if visited >= MAX_PATHS {
+        warn!(
+            "the call graph contains too many paths; the walk stopped after {} paths so the output \
+             is incomplete",
+            MAX_PATHS
+        );
+    }
+
     Ok(())
 }
 
+// nodes that are not called by other nodes; if every node is part of a cycle then we have no
+// better choice than the nodes with the largest stack usage
+fn roots(g: &Graph<Node, Edge>) -> Vec<NodeIndex> {
+    let roots = g
+        .externals(Direction::Incoming)
+        .filter(|idx| !g[*idx].dashed && !g[*idx].inlined)
+        .collect::<Vec<_>>();
+
+    if !roots.is_empty() {
+        return roots;
+    }
+
+    let largest = g.node_indices().map(|idx| max_value(g[idx].max)).max();
+    g.node_indices()
+        .filter(|idx| Some(max_value(g[*idx].max)) == largest)
+        .collect()
+}
+
+fn max_value(max: Option<Max>) -> u64 {
+    match max {
+        Some(Max::Exact(n)) | Some(Max::LowerBound(n)) => n,
+        None => 0,
+    }
+}
+
 pub(crate) struct Escaper<W>
 where
     W: io::Write,
@@ -1044,9 +2184,21 @@ where
 #[derive(Clone)]
 struct Node<'a> {
     name: Cow<'a, str>,
+    // original symbol name, if `name` has been shortened
+    mangled: Option<Cow<'a, str>>,
     local: Local,
     max: Option<Max>,
     dashed: bool,
+    // start address of the function, if it's a real symbol
+    address: Option<u64>,
+    // source location of the function's entry point
+    loc: Option<Loc>,
+    // this is not a real function but one that was inlined into its caller
+    inlined: bool,
+    // `local` or `max_assumed` was given by the user
+    assumed: bool,
+    // maximum stack usage given by the user; callees are not considered
+    max_assumed: Option<u64>,
 }
 
 #[allow(non_snake_case)]
@@ -1056,9 +2208,328 @@ where
 {
     Node {
         name: name.into(),
+        mangled: None,
         local: stack.map(Local::Exact).unwrap_or(Local::Unknown),
         max: None,
         dashed,
+        address: None,
+        loc: None,
+        inlined: false,
+        assumed: false,
+        max_assumed: None,
+    }
+}
+
+// maps the `nodes` of a graph to the nodes of one of its subgraphs; nodes that are not part of the
+// subgraph are dropped
+fn remap(nodes: &[NodeIndex], one2two: &BTreeMap<NodeIndex, NodeIndex>) -> Vec<NodeIndex> {
+    nodes
+        .iter()
+        .filter_map(|node| one2two.get(node).copied())
+        .collect()
+}
+
+// remaps the roots of each stack to the nodes of the subgraph of `g` given by `one2two`, warning
+// about the roots that are not part of it; `reason` says why they were left out
+fn remap_stacks(
+    g: &Graph<Node, Edge>,
+    stacks: &[stacks::Stack],
+    stack_roots: &mut [Vec<NodeIndex>],
+    one2two: &BTreeMap<NodeIndex, NodeIndex>,
+    reason: &str,
+) {
+    for (stack, roots) in stacks.iter().zip(stack_roots) {
+        let dropped = roots
+            .iter()
+            .filter(|root| !one2two.contains_key(root))
+            .map(|root| rustc_demangle::demangle(&g[*root].name).to_string())
+            .collect::<Vec<_>>();
+        if !dropped.is_empty() {
+            warn!(
+                "leaving out {} root(s) of stack `{}` ({}): {}; the usage of the stack doesn't \
+                 include them",
+                dropped.len(),
+                stack.name,
+                reason,
+                dropped.join(", ")
+            );
+        }
+
+        *roots = remap(roots, one2two);
+    }
+}
+
+// returns a copy of `g` that only contains the nodes for which `keep` returns `true`, and a map
+// from `g`'s `NodeIndex`-es to the new graph's `NodeIndex`-es
+fn subgraph<'a>(
+    g: &Graph<Node<'a>, Edge>,
+    keep: impl Fn(NodeIndex) -> bool,
+) -> (Graph<Node<'a>, Edge>, BTreeMap<NodeIndex, NodeIndex>) {
+    let mut g2 = DiGraph::<Node, Edge>::new();
+
+    let mut one2two = BTreeMap::new();
+    for idx in g.node_indices() {
+        if keep(idx) {
+            one2two.insert(idx, g2.add_node(g[idx].clone()));
+        }
+    }
+
+    for edge in g.raw_edges() {
+        if let (Some(caller), Some(callee)) =
+            (one2two.get(&edge.source()), one2two.get(&edge.target()))
+        {
+            g2.add_edge(*caller, *callee, edge.weight.clone());
+        }
+    }
+
+    (g2, one2two)
+}
+
+/// A "calls" relationship
+#[derive(Clone)]
+struct Edge {
+    origin: Origin,
+    // the call instructions that make up this edge; empty if unknown
+    sites: Vec<CallSite>,
+}
+
+impl Edge {
+    fn new(origin: Origin) -> Self {
+        Edge {
+            origin,
+            sites: vec![],
+        }
+    }
+
+    // whether the callee is always reached through a tail call
+    fn is_tail_call(&self) -> bool {
+        !self.sites.is_empty() && self.sites.iter().all(|site| site.tail)
+    }
+
+    // stack used by the caller while the callee runs. A tail call releases (most of) the caller's
+    // stack frame before branching so only what remains of the frame is added to the callee's stack
+    // usage; if the frame size at the branch is unknown we conservatively use the whole frame
+    fn caller_stack(&self, local: Local) -> Local {
+        if !self.is_tail_call() {
+            return local;
+        }
+
+        self.sites
+            .iter()
+            .map(|site| site.frame)
+            .try_fold(0, |max, frame| frame.map(|frame| cmp::max(max, frame)))
+            .map(Local::Exact)
+            .unwrap_or(local)
+    }
+}
+
+/// A call instruction
+#[derive(Clone, Debug)]
+struct CallSite {
+    // address of the branch instruction; only known for sites found in the machine code
+    address: Option<u64>,
+    // source location of the call
+    loc: Option<Loc>,
+    // functions inlined at the call site, from the outermost to the innermost
+    inlined: Vec<Frame>,
+    // `B` instruction that leaves the function (tail call) rather than a `BL` instruction
+    tail: bool,
+    // stack frame size of the caller at the tail call, if known
+    frame: Option<u64>,
+}
+
+impl CallSite {
+    // call site found in the LLVM-IR
+    fn ir(loc: Option<Loc>) -> Self {
+        CallSite {
+            address: None,
+            loc,
+            inlined: vec![],
+            tail: false,
+            frame: None,
+        }
+    }
+
+    // call site found in the machine code; `tail` is `Some` for tail calls and contains the stack
+    // frame size of the caller at the branch instruction, if known
+    fn at(address: u64, tail: Option<Option<u64>>, debug_info: Option<&DebugInfo>) -> Self {
+        CallSite {
+            address: Some(address),
+            loc: debug_info.and_then(|debug_info| debug_info.location(address)),
+            inlined: debug_info
+                .map(|debug_info| debug_info.inline_stack(address))
+                .unwrap_or_default(),
+            tail: tail.is_some(),
+            frame: tail.flatten(),
+        }
+    }
+}
+
+impl fmt::Display for CallSite {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match (&self.loc, self.address) {
+            (Some(loc), _) => write!(f, "{}", loc)?,
+            (None, Some(address)) => write!(f, "{:#x}", address)?,
+            (None, None) => f.write_str("?")?,
+        }
+
+        if self.tail {
+            f.write_str(" (tail call)")?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Why an edge exists in the call graph
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, Ord, PartialEq, PartialOrd, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+enum Origin {
+    /// A direct function call in the LLVM-IR
+    LlvmIr,
+    /// A call to a `mem*` routine assumed from a `llvm.mem*` intrinsic in the LLVM-IR
+    Intrinsic,
+    /// A `BL`, `B` or indirect branch instruction found in the machine code
+    MachineCode,
+    /// An indirect call resolved by matching function signatures
+    Signature,
+}
+
+impl fmt::Display for Origin {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Origin::LlvmIr => "llvm-ir",
+            Origin::Intrinsic => "intrinsic",
+            Origin::MachineCode => "machine-code",
+            Origin::Signature => "signature",
+        })
+    }
+}
+
+// the size of a function; if the symbol has no size, it's derived from the `$t` tags
+fn symbol_size(address: u32, size: u32, tags: &[(u32, Tag)]) -> u32 {
+    if size != 0 {
+        return size;
+    }
+
+    // try harder at finding out the size of this symbol
+    if let Ok(needle) = tags.binary_search_by(|tag| tag.0.cmp(&address)) {
+        let start = tags[needle];
+        if start.1 == Tag::Thumb {
+            if let Some(end) = tags.get(needle + 1) {
+                if end.1 == Tag::Thumb {
+                    return end.0 - start.0;
+                }
+            }
+        }
+    }
+
+    0
+}
+
+// the targets of branches: the defined symbols of all the code sections (functions and linker
+// veneers), indexed by address; `node` falls back to the ROM table and to the unnamed regions of
+// the code sections for the addresses that no symbol covers
+struct Functions<'a> {
+    // address ranges of the code sections
+    code: Vec<ops::Range<u64>>,
+    // start address -> (end address, canonical name)
+    ranges: BTreeMap<u64, (u64, &'a str)>,
+}
+
+impl<'a> Functions<'a> {
+    // returns the node that a branch to `address`, made by `caller`, goes to
+    fn node(
+        &self,
+        address: u64,
+        caller: &str,
+        rom: &'a rom::Rom,
+        g: &mut Graph<Node<'a>, Edge>,
+        indices: &mut BTreeMap<Cow<'a, str>, NodeIndex>,
+    ) -> NodeIndex {
+        let containing = self.ranges.range(..=address).next_back();
+
+        match containing {
+            Some((start, (_, name))) if *start == address => return indices[*name],
+            Some((start, (end, name))) if address < *end => {
+                warn!(
+                    "`{}` branches to address {:#x}, which is {} bytes into `{}`; it will be \
+                     treated as a call to `{}`",
+                    caller,
+                    address,
+                    address - start,
+                    name,
+                    name
+                );
+                return indices[*name];
+            }
+            _ => {}
+        }
+
+        if let Some(entry) = rom.find(address) {
+            return entry.node(g, indices);
+        }
+
+        // the region of the code section between two functions; outside the code sections the
+        // address itself is used
+        let region = if let Some(section) = self.code.iter().find(|sect| sect.contains(&address)) {
+            let start = containing
+                .map(|(_, (end, _))| *end)
+                .filter(|end| *end >= section.start)
+                .unwrap_or(section.start);
+            let end = self
+                .ranges
+                .range(address + 1..)
+                .next()
+                .map(|(start, _)| *start)
+                .filter(|start| *start <= section.end)
+                .unwrap_or(section.end);
+            start..end
+        } else {
+            address..address + 1
+        };
+
+        let name = if region.end - region.start > 1 {
+            format!("{:#x}..{:#x}", region.start, region.end)
+        } else {
+            format!("{:#x}", region.start)
+        };
+        warn!(
+            "`{}` branches to address {:#x}, which is not part of any function; it will be \
+             treated as a call to the unnamed region `{}`",
+            caller, address, name
+        );
+
+        if let Some(idx) = indices.get(name.as_str()) {
+            return *idx;
+        }
+
+        let idx = g.add_node(Node(name.clone(), None, false));
+        g[idx].address = Some(region.start);
+        indices.insert(name.into(), idx);
+        idx
+    }
+}
+
+// records a call from `caller` to `callee`, adding the edge if it doesn't exist yet
+fn add_call(
+    g: &mut Graph<Node, Edge>,
+    caller: NodeIndex,
+    callee: NodeIndex,
+    origin: Origin,
+    site: Option<CallSite>,
+) {
+    let edge = g
+        .find_edge(caller, callee)
+        .unwrap_or_else(|| g.add_edge(caller, callee, Edge::new(origin)));
+
+    if let Some(site) = site {
+        let sites = &mut g[edge].sites;
+        // the machine code is the ground truth; it replaces the call sites found in the LLVM-IR
+        if site.address.is_some() && sites.iter().all(|site| site.address.is_none()) {
+            sites.clear();
+        }
+        sites.push(site);
     }
 }
 
@@ -1087,7 +2558,7 @@ impl Into<Max> for Local {
     }
 }
 
-#[derive(Clone, Copy, Eq, PartialEq)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
 enum Max {
     Exact(u64),
     LowerBound(u64),
@@ -1119,6 +2590,32 @@ impl ops::Add<Max> for Max {
     }
 }
 
+// the maximum stack usage given by the user or, if none was given, the one computed from the
+// callees
+fn max_or_assumed(g: &Graph<Node, Edge>, node: NodeIndex) -> Max {
+    if let Some(max) = g[node].max_assumed {
+        Max::Exact(max)
+    } else {
+        max_through_callees(g, node)
+    }
+}
+
+// computes the max stack usage of `node` from the max stack usage of its callees
+fn max_through_callees(g: &Graph<Node, Edge>, node: NodeIndex) -> Max {
+    let local = g[node].local;
+
+    let callees_max = max_of(g.edges_directed(node, Direction::Outgoing).map(|edge| {
+        g[edge.target()].max.expect("UNREACHABLE") + edge.weight().caller_stack(local)
+    }));
+
+    if let Some(callees_max) = callees_max {
+        // a tail call can use less stack than the caller itself
+        max(callees_max, local.into())
+    } else {
+        local.into()
+    }
+}
+
 fn max_of(mut iter: impl Iterator<Item = Max>) -> Option<Max> {
     iter.next().map(|first| iter.fold(first, max))
 }
@@ -1145,7 +2642,7 @@ impl fmt::Display for Max {
 #[derive(Default, Debug)]
 struct Indirect {
     called: bool,
-    callers: HashSet<NodeIndex>,
+    callers: HashMap<NodeIndex, Vec<CallSite>>,
     callees: HashSet<NodeIndex>,
 }
 
@@ -1174,12 +2671,13 @@ enum Target {
     Other,
     Thumbv6m,
     Thumbv7m,
+    Thumbv8m,
 }
 
 impl Target {
     fn is_thumb(&self) -> bool {
         match *self {
-            Target::Thumbv6m | Target::Thumbv7m => true,
+            Target::Thumbv6m | Target::Thumbv7m | Target::Thumbv8m => true,
             Target::Other => false,
         }
     }
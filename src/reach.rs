@@ -0,0 +1,290 @@
+use core::cmp;
+use std::{
+    collections::{hash_map::Entry, HashMap, HashSet, VecDeque},
+    io::{self, Write},
+};
+
+use petgraph::{
+    algo,
+    graph::{Graph, NodeIndex},
+    visit::{Dfs, EdgeRef, Reversed},
+    Direction,
+};
+
+use crate::{max_of, max_value, start::Pattern, Edge, Max, Node};
+
+/// Returns the nodes that can reach any of the `targets`, including the `targets` themselves
+pub(crate) fn ancestors(g: &Graph<Node, Edge>, targets: &[NodeIndex]) -> HashSet<NodeIndex> {
+    let mut ancestors = HashSet::new();
+
+    let mut dfs = Dfs::empty(Reversed(g));
+    for target in targets {
+        dfs.move_to(*target);
+        while let Some(node) = dfs.next(Reversed(g)) {
+            ancestors.insert(node);
+        }
+    }
+
+    ancestors
+}
+
+/// Returns the `excluded` nodes plus the nodes that can only be reached through them
+///
+/// A node is kept if it can be reached from one of the `entries`, or from a node without callers,
+/// without going through an excluded node. This also removes the cycles that can only be reached
+/// through an excluded node, which have callers of their own
+pub(crate) fn excluded(
+    g: &Graph<Node, Edge>,
+    excluded: &HashSet<NodeIndex>,
+    entries: &[NodeIndex],
+) -> HashSet<NodeIndex> {
+    let mut kept = HashSet::new();
+    let mut stack = g
+        .externals(Direction::Incoming)
+        .chain(entries.iter().copied())
+        .filter(|idx| !excluded.contains(idx))
+        .collect::<Vec<_>>();
+    while let Some(node) = stack.pop() {
+        if kept.insert(node) {
+            stack.extend(
+                g.neighbors_directed(node, Direction::Outgoing)
+                    .filter(|callee| !excluded.contains(callee)),
+            );
+        }
+    }
+
+    // only the nodes reachable from the excluded ones are removed
+    let mut removed = excluded.clone();
+    let mut stack = excluded.iter().copied().collect::<Vec<_>>();
+    while let Some(node) = stack.pop() {
+        for callee in g.neighbors_directed(node, Direction::Outgoing) {
+            if !kept.contains(&callee) && removed.insert(callee) {
+                stack.push(callee);
+            }
+        }
+    }
+
+    removed
+}
+
+/// Computes, for each node, the maximum amount of stack that's in use when one of the `targets` is
+/// entered from that node
+///
+/// The result is indexed by `NodeIndex`; it's `None` for nodes that can't reach any target. The
+/// depth is a lower bound if the path to the target goes through a cycle
+pub(crate) fn depths(g: &Graph<Node, Edge>, targets: &[NodeIndex]) -> Vec<Option<Max>> {
+    let mut depths = vec![None; g.node_count()];
+
+    // SCCs come in reverse topological order: callees are visited before their callers
+    for scc in algo::kosaraju_scc(g) {
+        let is_a_cycle = scc.len() > 1
+            || g.neighbors_directed(scc[0], Direction::Outgoing)
+                .any(|n| n == scc[0]);
+
+        // stack in use when a target is entered through an edge that leaves the SCC
+        let exits = max_of(scc.iter().flat_map(|caller| {
+            let depths = &depths;
+            g.edges_directed(*caller, Direction::Outgoing)
+                .filter(|edge| !scc.contains(&edge.target()))
+                .filter_map(move |edge| {
+                    depths[edge.target().index()]
+                        .map(|depth: Max| depth + edge.weight().caller_stack(g[*caller].local))
+                })
+        }));
+
+        for node in &scc {
+            depths[node.index()] = if targets.contains(node) {
+                Some(Max::Exact(0))
+            } else if let Some(depth) = exits {
+                if is_a_cycle {
+                    // the cycle can be entered any number of times before reaching the target
+                    Some(Max::LowerBound(max_value(Some(depth))))
+                } else {
+                    Some(depth)
+                }
+            } else if is_a_cycle && scc.iter().any(|node| targets.contains(node)) {
+                // the target is part of this cycle
+                Some(Max::LowerBound(0))
+            } else {
+                None
+            };
+        }
+    }
+
+    depths
+}
+
+/// Prints to stderr the stack in use when the target is reached from each root, deepest first
+pub(crate) fn report(
+    g: &Graph<Node, Edge>,
+    roots: &[NodeIndex],
+    depths: &[Option<Max>],
+) -> io::Result<()> {
+    let stderr = io::stderr();
+    let mut stderr = stderr.lock();
+
+    let mut roots = roots
+        .iter()
+        .filter_map(|root| depths[root.index()].map(|depth| (*root, depth)))
+        .collect::<Vec<_>>();
+    roots.sort_by_key(|(_, depth)| cmp::Reverse(max_value(Some(*depth))));
+
+    writeln!(stderr, "Depth Root")?;
+    for (root, depth) in roots {
+        let depth = match depth {
+            Max::Exact(n) => n.to_string(),
+            Max::LowerBound(n) => format!(">={}", n),
+        };
+
+        writeln!(
+            stderr,
+            "{} {}",
+            depth,
+            rustc_demangle::demangle(&g[root].name)
+        )?;
+    }
+
+    Ok(())
+}
+
+// functions through which every panic goes
+const PANIC_ENTRY_POINTS: &[&str] = &[
+    "core::panicking::*",
+    "*::panic_bounds_check",
+    "rust_begin_unwind",
+    "__rust_start_panic",
+];
+
+/// Prints, for each root, whether it can reach a panic entry point along with an example call
+/// chain and returns `true` if any of the roots may panic
+///
+/// Calls to unknown functions (`?` nodes) are treated as potential panics
+pub(crate) fn panics(g: &Graph<Node, Edge>, roots: &[NodeIndex]) -> io::Result<bool> {
+    let stdout = io::stdout();
+    let mut stdout = stdout.lock();
+
+    let entry_points = PANIC_ENTRY_POINTS
+        .iter()
+        .map(|pattern| Pattern::new(pattern).expect("UNREACHABLE"))
+        .collect::<Vec<_>>();
+    let panics = |idx: NodeIndex| {
+        entry_points
+            .iter()
+            .any(|pattern| pattern.matches(&g[idx].name))
+    };
+    let unknown = |idx: NodeIndex| g[idx].name == "?";
+
+    let mut may_panic = false;
+    writeln!(stdout, "Panics Root")?;
+    for root in roots {
+        let (status, chain) = if let Some(chain) = chain(g, *root, panics) {
+            ("yes", Some(chain))
+        } else if let Some(chain) = chain(g, *root, unknown) {
+            ("maybe", Some(chain))
+        } else {
+            ("no", None)
+        };
+
+        writeln!(
+            stdout,
+            "{} {}",
+            status,
+            rustc_demangle::demangle(&g[*root].name)
+        )?;
+
+        if let Some(chain) = chain {
+            may_panic = true;
+
+            let chain = chain
+                .iter()
+                .map(|idx| rustc_demangle::demangle(&g[*idx].name).to_string())
+                .collect::<Vec<_>>();
+            writeln!(stdout, "  {}", chain.join(" -> "))?;
+        }
+    }
+
+    Ok(may_panic)
+}
+
+// shortest call chain from `from` to a node for which `is_target` returns `true`
+fn chain(
+    g: &Graph<Node, Edge>,
+    from: NodeIndex,
+    is_target: impl Fn(NodeIndex) -> bool,
+) -> Option<Vec<NodeIndex>> {
+    // breadth-first search; `parents` doubles as the set of visited nodes
+    let mut parents = HashMap::new();
+    parents.insert(from, None);
+    let mut queue = VecDeque::new();
+    queue.push_back(from);
+
+    while let Some(node) = queue.pop_front() {
+        if is_target(node) {
+            let mut chain = vec![node];
+            let mut next = parents[&node];
+            while let Some(parent) = next {
+                chain.push(parent);
+                next = parents[&parent];
+            }
+            chain.reverse();
+            return Some(chain);
+        }
+
+        for callee in g.neighbors_directed(node, Direction::Outgoing) {
+            if let Entry::Vacant(entry) = parents.entry(callee) {
+                entry.insert(Some(node));
+                queue.push_back(callee);
+            }
+        }
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use petgraph::graph::Graph;
+
+    use crate::{Edge, Node, Origin};
+
+    #[test]
+    fn excluded() {
+        // main -> log -> { fmt <-> write }, main -> work -> write
+        let mut g = Graph::new();
+        let main = g.add_node(Node("main", Some(0), false));
+        let log = g.add_node(Node("log", Some(0), false));
+        let fmt = g.add_node(Node("fmt", Some(0), false));
+        let write = g.add_node(Node("write", Some(0), false));
+        let panic = g.add_node(Node("panic", Some(0), false));
+        let a = g.add_node(Node("a", Some(0), false));
+        let b = g.add_node(Node("b", Some(0), false));
+        for (caller, callee) in [
+            (main, log),
+            (log, fmt),
+            (fmt, write),
+            (write, fmt),
+            (main, panic),
+            (panic, a),
+            (a, b),
+            (b, a),
+        ] {
+            g.add_edge(caller, callee, Edge::new(Origin::LlvmIr));
+        }
+
+        // the `a <-> b` cycle is only reachable through `panic`
+        let removed = super::excluded(&g, &[panic].iter().copied().collect(), &[main]);
+        assert_eq!(
+            removed,
+            [panic, a, b].iter().copied().collect::<HashSet<_>>()
+        );
+
+        // the `fmt <-> write` cycle is only reachable through `log`
+        let removed = super::excluded(&g, &[log].iter().copied().collect(), &[main]);
+        assert_eq!(
+            removed,
+            [log, fmt, write].iter().copied().collect::<HashSet<_>>()
+        );
+    }
+}
@@ -0,0 +1,181 @@
+use std::{borrow::Cow, collections::BTreeMap, fs, path::Path};
+
+use anyhow::anyhow;
+use petgraph::graph::{Graph, NodeIndex};
+use xmas_elf::{
+    sections::{self, SectionData},
+    symbol_table::{Entry as _, Type},
+    ElfFile,
+};
+
+use crate::{Edge, Node};
+
+/// A function, or a region of functions, in mask ROM or in other memory that's not part of the
+/// ELF file
+pub(crate) struct Entry {
+    pub(crate) name: String,
+    pub(crate) start: u64,
+    /// End of the region (exclusive); `None` if the entry is a single function
+    pub(crate) end: Option<u64>,
+    /// Assumed maximum stack usage
+    pub(crate) max: Option<u64>,
+}
+
+impl Entry {
+    /// Returns the node that represents this entry, adding it to the graph if needed
+    pub(crate) fn node<'a>(
+        &'a self,
+        g: &mut Graph<Node<'a>, Edge>,
+        indices: &mut BTreeMap<Cow<'a, str>, NodeIndex>,
+    ) -> NodeIndex {
+        if let Some(idx) = indices.get(self.name.as_str()) {
+            return *idx;
+        }
+
+        let idx = g.add_node(Node(self.name.as_str(), None, false));
+        g[idx].address = Some(self.start);
+        if let Some(max) = self.max {
+            g[idx].max_assumed = Some(max);
+            g[idx].assumed = true;
+        }
+        indices.insert(self.name.as_str().into(), idx);
+        idx
+    }
+}
+
+/// The functions that the program can call but are not defined in the ELF file
+#[derive(Default)]
+pub(crate) struct Rom {
+    entries: Vec<Entry>,
+}
+
+impl Rom {
+    /// Loads a ROM table
+    ///
+    /// Each line has the form `START[-END] NAME [MAX]` where `START` and `END` are hexadecimal
+    /// addresses, e.g. `0x0000_0100-0x0000_4000 bootrom 64`; empty lines and lines that start with
+    /// `#` are ignored
+    pub(crate) fn load(path: &Path) -> anyhow::Result<Self> {
+        let contents = fs::read_to_string(path)
+            .map_err(|e| anyhow!("couldn't open ROM table `{}`: {}", path.display(), e))?;
+
+        let mut entries = vec![];
+        for (i, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let error = || {
+                anyhow!(
+                    "{}:{}: expected `START[-END] NAME [MAX]`, found `{}`",
+                    path.display(),
+                    i + 1,
+                    line
+                )
+            };
+
+            let mut parts = line.split_whitespace();
+            let range = parts.next().ok_or_else(error)?;
+            let (start, end) = match range.split_once('-') {
+                Some((start, end)) => (start, Some(end)),
+                None => (range, None),
+            };
+            let start = hex(start).ok_or_else(error)?;
+            let end = end.map(|end| hex(end).ok_or_else(error)).transpose()?;
+            let name = parts.next().ok_or_else(error)?.to_owned();
+            let max = parts
+                .next()
+                .map(|max| max.parse::<u64>().map_err(|_| error()))
+                .transpose()?;
+            if parts.next().is_some() || end.map(|end| end <= start) == Some(true) {
+                return Err(error());
+            }
+
+            entries.push(Entry {
+                name,
+                start,
+                end,
+                max,
+            });
+        }
+
+        Ok(Rom { entries })
+    }
+
+    /// Adds the absolute symbols of the ELF file, like the ones defined with `PROVIDE` in linker
+    /// scripts
+    ///
+    /// Entries loaded from a ROM table take precedence over these symbols
+    pub(crate) fn add_absolute_symbols(&mut self, elf: &ElfFile, thumb: bool) {
+        let sect = if let Some(sect) = elf.find_section_by_name(".symtab") {
+            sect
+        } else {
+            return;
+        };
+
+        let symbols = match sect.get_data(elf) {
+            Ok(SectionData::SymbolTable32(entries)) => entries
+                .iter()
+                .filter(|entry| entry.shndx() == sections::SHN_ABS)
+                .filter(|entry| matches!(entry.get_type(), Ok(Type::Func) | Ok(Type::NoType)))
+                .filter_map(|entry| Some((entry.get_name(elf).ok()?, entry.value())))
+                .collect::<Vec<_>>(),
+            Ok(SectionData::SymbolTable64(entries)) => entries
+                .iter()
+                .filter(|entry| entry.shndx() == sections::SHN_ABS)
+                .filter(|entry| matches!(entry.get_type(), Ok(Type::Func) | Ok(Type::NoType)))
+                .filter_map(|entry| Some((entry.get_name(elf).ok()?, entry.value())))
+                .collect::<Vec<_>>(),
+            _ => return,
+        };
+
+        for (name, value) in symbols {
+            // skip mapping symbols (`$t`, `$d`, etc.)
+            if name.is_empty() || name.starts_with('$') {
+                continue;
+            }
+
+            // clear the thumb bit
+            let start = if thumb { value & !1 } else { value };
+            self.entries.push(Entry {
+                name: name.to_owned(),
+                start,
+                end: None,
+                max: None,
+            });
+        }
+    }
+
+    /// Returns the entry for the symbol `name`
+    ///
+    /// If a ROM table entry covers the address of the symbol, that entry is returned instead
+    pub(crate) fn get(&self, name: &str) -> Option<&Entry> {
+        let entry = self.entries.iter().find(|entry| entry.name == name)?;
+        self.find(entry.start)
+    }
+
+    /// Returns the entry that contains `address`
+    pub(crate) fn find(&self, address: u64) -> Option<&Entry> {
+        self.entries.iter().find(|entry| match entry.end {
+            Some(end) => entry.start <= address && address < end,
+            None => entry.start == address,
+        })
+    }
+}
+
+// parses a hexadecimal address, e.g. `0x0000_0100`
+fn hex(s: &str) -> Option<u64> {
+    let s = s.strip_prefix("0x").or_else(|| s.strip_prefix("0X"))?;
+    u64::from_str_radix(&s.replace('_', ""), 16).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn hex() {
+        assert_eq!(super::hex("0x0000_0100"), Some(0x100));
+        assert_eq!(super::hex("0X1f"), Some(0x1f));
+        assert_eq!(super::hex("100"), None);
+    }
+}
@@ -0,0 +1,443 @@
+use core::ops;
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    fs,
+    io::{self, Write},
+    path::Path,
+};
+
+use anyhow::{anyhow, bail};
+use log::warn;
+use petgraph::{
+    graph::{Graph, NodeIndex},
+    visit::Dfs,
+    Direction,
+};
+
+use crate::{dehash, ir, max, start::Pattern, Edge, Max, Node};
+
+// registers pushed onto the stack on exception entry: r0-r3, r12, lr, pc and xpsr
+const BASIC_FRAME: u64 = 32;
+// the basic frame plus s0-s15, fpscr and a reserved word
+const EXTENDED_FRAME: u64 = 104;
+// the stack pointer is realigned to 8 bytes on exception entry
+const ALIGNMENT_PADDING: u64 = 4;
+
+// prefix of the items that RTIC (v1 and v2) generates, like the ready queue of a priority level
+// (`__rtic_internal_P1_RQ`) or the resources of a task
+const INTERNAL: &str = "__rtic_internal_";
+
+// the Interrupt Set-Pending Registers of the NVIC
+const ISPR: ops::Range<u64> = 0xe000_e200..0xe000_e240;
+
+// the handlers that cortex-m-rt provides when the application doesn't define them; they are not
+// RTIC tasks and they don't return (or only run when the program has a bug)
+const RUNTIME: &[&str] = &[
+    "DefaultHandler",
+    "DefaultHandler_",
+    "HardFault",
+    "HardFault_",
+    "HardFaultTrampoline",
+];
+
+/// Task priorities, as listed in a priorities file
+///
+/// Each line contains a priority followed by a pattern that matches the name of an exception or
+/// interrupt handler, e.g. `2 EXTI0`; empty lines and lines that start with `#` are ignored
+pub(crate) struct Priorities {
+    entries: Vec<(u8, Pattern)>,
+}
+
+impl Priorities {
+    pub(crate) fn load(path: &Path) -> anyhow::Result<Self> {
+        let contents = fs::read_to_string(path)
+            .map_err(|e| anyhow!("couldn't open priorities file `{}`: {}", path.display(), e))?;
+
+        let mut entries = vec![];
+        for (i, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let mut parts = line.splitn(2, char::is_whitespace);
+            let priority = parts.next().and_then(|s| s.parse::<u8>().ok());
+            let pattern = parts.next().map(str::trim).filter(|s| !s.is_empty());
+            match (priority, pattern) {
+                (Some(priority), Some(pattern)) => entries.push((priority, Pattern::new(pattern)?)),
+                _ => bail!(
+                    "{}:{}: expected a priority followed by a function name, found `{}`",
+                    path.display(),
+                    i + 1,
+                    line
+                ),
+            }
+        }
+
+        Ok(Priorities { entries })
+    }
+
+    fn get(&self, name: &str) -> Option<u8> {
+        self.entries
+            .iter()
+            .find(|(_, pattern)| pattern.matches(name))
+            .map(|(priority, _)| *priority)
+    }
+}
+
+/// Whether the program contains items generated by RTIC
+pub(crate) fn is_rtic(defines: &HashMap<&str, &ir::Function>) -> bool {
+    defines
+        .values()
+        .any(|f| is_internal(&f.name) || f.refs.iter().any(|name| is_internal(name)))
+}
+
+// the path of an item without the hash, e.g. `app::__rtic_internal_P1_RQ`
+fn path(name: &str) -> String {
+    let demangled = rustc_demangle::demangle(name).to_string();
+    dehash(&demangled).unwrap_or(&demangled).to_owned()
+}
+
+// the last component of the path of the item `name`
+fn item(name: &str) -> String {
+    let path = path(name);
+    path.rsplit("::").next().unwrap_or(&path).to_owned()
+}
+
+fn is_internal(name: &str) -> bool {
+    item(name).starts_with(INTERNAL)
+}
+
+// the priority level of the ready queue `__rtic_internal_P{level}_RQ` of RTIC v1; the dispatcher
+// of a level drains its ready queue
+fn ready_queue(name: &str) -> Option<u8> {
+    item(name)
+        .strip_prefix(INTERNAL)?
+        .strip_prefix('P')?
+        .strip_suffix("_RQ")?
+        .parse()
+        .ok()
+}
+
+// the priority of a software task dispatcher, derived from the ready queue it drains
+//
+// `functions` are the functions that run when the handler runs. Spawning a software task enqueues
+// it in the ready queue of its priority and pends the interrupt of its dispatcher, so a handler
+// that spawns tasks also uses a ready queue; dispatchers don't pend interrupts. The priority is
+// only derived if none of the functions pends an interrupt and they only use the ready queue of one
+// priority level
+fn dispatcher(functions: &[&ir::Function]) -> Option<u8> {
+    if functions.iter().any(|f| pends(f)) {
+        return None;
+    }
+
+    let levels = functions
+        .iter()
+        .flat_map(|f| &f.refs)
+        .filter_map(|name| ready_queue(name))
+        .collect::<BTreeSet<_>>();
+    if levels.len() == 1 {
+        levels.into_iter().next()
+    } else {
+        None
+    }
+}
+
+// whether `f` pends an interrupt, by calling a `pend` function (`rtic::pend`, `NVIC::pend`) or by
+// writing to a set-pending register of the NVIC
+fn pends(f: &ir::Function) -> bool {
+    f.writes.iter().any(|address| ISPR.contains(address))
+        || f.callees.iter().any(|callee| match callee {
+            ir::Callee::Direct(callee) => item(&callee.name) == "pend",
+            _ => false,
+        })
+}
+
+/// An exception or interrupt handler: an RTIC hardware task or a software task dispatcher
+pub(crate) struct Task {
+    pub(crate) node: NodeIndex,
+    /// `None` if the priority is not known
+    pub(crate) priority: Option<u8>,
+}
+
+/// Classifies the handlers in the vector table
+///
+/// `handlers` are the nodes listed in the vector table, in order; `None` if the handler is not in
+/// the graph. The first one is the reset handler, which runs `init` and `idle` in thread mode;
+/// it's returned separately. The handlers that cortex-m-rt provides are left out.
+///
+/// RTIC doesn't keep the priority of a hardware task in the symbol table but the dispatcher of the
+/// software tasks of priority `N` drains the ready queue `__rtic_internal_P{N}_RQ` (RTIC v1) so its
+/// priority is derived from it (see `dispatcher`). Priorities listed in `priorities` take
+/// precedence
+pub(crate) fn tasks(
+    g: &Graph<Node, Edge>,
+    handlers: &[Option<NodeIndex>],
+    defines: &HashMap<&str, &ir::Function>,
+    priorities: Option<&Priorities>,
+) -> (Option<NodeIndex>, Vec<Task>) {
+    let (reset, handlers) = match handlers.split_first() {
+        Some((reset, handlers)) => (*reset, handlers),
+        None => (None, handlers),
+    };
+
+    // the modules that contain the RTIC application, e.g. `app`
+    let apps = defines
+        .values()
+        .flat_map(|f| f.refs.iter().chain(Some(&f.name)))
+        .filter(|name| is_internal(name))
+        .filter_map(|name| {
+            let path = path(name);
+            path.rsplit_once("::")
+                .map(|(module, _)| format!("{}::", module))
+        })
+        .collect::<BTreeSet<_>>();
+    let refs = |node: NodeIndex| {
+        defines
+            .get(&*g[node].name)
+            .map(|f| &f.refs[..])
+            .unwrap_or(&[])
+    };
+
+    let mut tasks = vec![];
+    let mut runtime = vec![];
+    let mut foreign = vec![];
+    for node in handlers.iter().flatten() {
+        // the same handler can be used for several vectors, e.g. `DefaultHandler`
+        if Some(*node) == reset
+            || tasks.iter().any(|task: &Task| task.node == *node)
+            || runtime.contains(node)
+        {
+            continue;
+        }
+
+        if RUNTIME.contains(&&*g[*node].name) {
+            runtime.push(*node);
+            continue;
+        }
+
+        // hardware tasks call the task function, which lives in the module of the application,
+        // unless it has been inlined; then the handler uses the resources RTIC generates for it
+        let generated = refs(*node).iter().any(|name| is_internal(name))
+            || g.neighbors_directed(*node, Direction::Outgoing)
+                .any(|callee| {
+                    apps.iter()
+                        .any(|app| path(&g[callee].name).starts_with(app))
+                });
+        if !generated {
+            foreign.push(*node);
+        }
+
+        // the functions that run when the handler runs
+        let mut functions = vec![];
+        let mut dfs = Dfs::new(g, *node);
+        while let Some(idx) = dfs.next(g) {
+            functions.extend(defines.get(&*g[idx].name).copied());
+        }
+        let derived = dispatcher(&functions);
+
+        tasks.push(Task {
+            node: *node,
+            priority: priorities
+                .and_then(|priorities| priorities.get(&g[*node].name))
+                .or(derived),
+        });
+    }
+
+    let names = |nodes: &[NodeIndex]| {
+        nodes
+            .iter()
+            .map(|node| name(g, *node).to_string())
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+
+    if !runtime.is_empty() {
+        warn!(
+            "ignoring the handlers provided by cortex-m-rt, which are not RTIC tasks: {}",
+            names(&runtime)
+        );
+    }
+    if !foreign.is_empty() {
+        warn!(
+            "{} handler(s) don't use any item generated by RTIC; including them as tasks: {}",
+            foreign.len(),
+            names(&foreign)
+        );
+    }
+
+    let unknown = tasks
+        .iter()
+        .filter(|task| task.priority.is_none())
+        .map(|task| task.node)
+        .collect::<Vec<_>>();
+    if !unknown.is_empty() {
+        warn!(
+            "the priority of {} task(s) is unknown; list them in the `--priorities` file. \
+             Assuming that each of them can preempt all the other tasks: {}",
+            unknown.len(),
+            names(&unknown)
+        );
+    }
+
+    (reset, tasks)
+}
+
+/// Computes the worst-case stack usage of the whole application and prints the breakdown to
+/// stderr
+///
+/// Tasks with the same priority can't preempt each other so only the largest task of each
+/// priority level contributes to the total, plus one exception frame per level. Tasks whose
+/// priority is unknown are assumed to be on a level of their own
+pub(crate) fn report(
+    g: &Graph<Node, Edge>,
+    reset: Option<NodeIndex>,
+    tasks: &[Task],
+    fpu: bool,
+) -> io::Result<()> {
+    let stderr = io::stderr();
+    let mut stderr = stderr.lock();
+
+    let frame = exception_frame(fpu);
+    // a function without stack usage information contributes an unknown amount of stack
+    let usage = |node: NodeIndex| g[node].max.unwrap_or(Max::LowerBound(0));
+
+    let mut levels = BTreeMap::<u8, Max>::new();
+    let mut unknown = vec![];
+    for task in tasks {
+        if let Some(priority) = task.priority {
+            let level = levels.entry(priority).or_insert(Max::Exact(0));
+            *level = max(*level, usage(task.node));
+        } else {
+            unknown.push(usage(task.node));
+        }
+    }
+
+    writeln!(stderr, "Priority Max Task")?;
+    if let Some(reset) = reset {
+        writeln!(stderr, "0 {} {}", bytes(usage(reset)), name(g, reset))?;
+    }
+    let mut sorted = tasks.iter().collect::<Vec<_>>();
+    // unknown priorities last
+    sorted.sort_by_key(|task| (task.priority.is_none(), task.priority));
+    for task in sorted {
+        let priority = task
+            .priority
+            .map(|priority| priority.to_string())
+            .unwrap_or_else(|| "?".to_owned());
+        writeln!(
+            stderr,
+            "{} {} {}",
+            priority,
+            bytes(usage(task.node)),
+            name(g, task.node)
+        )?;
+    }
+
+    let preemptions = (levels.len() + unknown.len()) as u64;
+    let total = levels
+        .values()
+        .chain(&unknown)
+        .fold(reset.map(usage).unwrap_or(Max::Exact(0)), |total, max| {
+            total + *max
+        })
+        + Max::Exact(preemptions * frame);
+
+    writeln!(
+        stderr,
+        "{} worst case ({} preemption level(s), {} bytes per exception frame)",
+        bytes(total),
+        preemptions,
+        frame
+    )?;
+
+    Ok(())
+}
+
+/// Number of bytes the hardware pushes onto the stack when an exception preempts the running code;
+/// `fpu` is whether the FPU registers are also stacked
+pub(crate) fn exception_frame(fpu: bool) -> u64 {
+    (if fpu { EXTENDED_FRAME } else { BASIC_FRAME }) + ALIGNMENT_PADDING
+}
+
+fn bytes(max: Max) -> String {
+    match max {
+        Max::Exact(n) => n.to_string(),
+        Max::LowerBound(n) => format!(">={}", n),
+    }
+}
+
+fn name<'a>(g: &'a Graph<Node, Edge>, node: NodeIndex) -> rustc_demangle::Demangle<'a> {
+    rustc_demangle::demangle(&g[node].name)
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn ready_queue() {
+        assert_eq!(
+            super::ready_queue("_ZN3app21__rtic_internal_P1_RQ17h0123456789abcdefE"),
+            Some(1)
+        );
+        assert_eq!(
+            super::ready_queue("_ZN3app26__rtic_internal_foo_INPUTS17h0123456789abcdefE"),
+            None
+        );
+        assert_eq!(super::ready_queue("UART0"), None);
+    }
+
+    #[test]
+    fn spawner_and_dispatcher() {
+        use std::collections::HashMap;
+
+        use petgraph::graph::Graph;
+
+        use crate::{
+            ir::{Callee, DirectCallee, Function},
+            Node,
+        };
+
+        const RQ: &str = "_ZN3app21__rtic_internal_P1_RQ17h0123456789abcdefE";
+        let function = |name: &str, callees: Vec<Callee>, writes: Vec<u64>| Function {
+            name: name.to_owned(),
+            sig: "void ()".to_owned(),
+            callees,
+            refs: vec![RQ.to_owned()],
+            writes,
+        };
+
+        // the dispatcher of priority 1 drains the ready queue; the hardware tasks spawn a
+        // software task of priority 1, pending the dispatcher with `rtic::pend` or writing to the
+        // NVIC
+        let ssi0 = function("SSI0", vec![], vec![]);
+        let uart0 = function(
+            "UART0",
+            vec![Callee::Direct(DirectCallee {
+                name: "_ZN4rtic4pend17h0123456789abcdefE".to_owned(),
+                loc: None,
+            })],
+            vec![],
+        );
+        let exti0 = function("EXTI0", vec![], vec![0xe000_e200]);
+        let defines = [&ssi0, &uart0, &exti0]
+            .iter()
+            .map(|f| (f.name.as_str(), *f))
+            .collect::<HashMap<_, _>>();
+
+        let mut g = Graph::new();
+        let reset = g.add_node(Node("Reset", Some(0), false));
+        let handlers = ["SSI0", "UART0", "EXTI0"]
+            .iter()
+            .map(|name| Some(g.add_node(Node(*name, Some(0), false))))
+            .collect::<Vec<_>>();
+
+        let (_, tasks) = super::tasks(
+            &g,
+            &[&[Some(reset)][..], &handlers].concat(),
+            &defines,
+            None,
+        );
+        let priorities = tasks.iter().map(|task| task.priority).collect::<Vec<_>>();
+        assert_eq!(priorities, [Some(1), None, None]);
+    }
+}
@@ -0,0 +1,258 @@
+use core::fmt::Write as _;
+use std::{
+    collections::BTreeMap,
+    io::{self, Write},
+};
+
+use anyhow::{anyhow, bail};
+use log::{error, warn};
+use petgraph::{
+    graph::{Graph, NodeIndex},
+    visit::Dfs,
+};
+
+use crate::{start::Pattern, Edge, Max, Node};
+
+/// A stack, e.g. the stack of one of the cores of a multi-core device
+pub(crate) struct Stack {
+    pub(crate) name: String,
+    /// Size of the stack, in bytes
+    pub(crate) budget: Option<u64>,
+    /// The entry points that run on this stack
+    pub(crate) roots: Vec<Pattern>,
+}
+
+/// Parses the `--stack` arguments, which have the form `NAME[@BUDGET]=PATTERN`
+///
+/// A stack can be given several times to specify several roots; the budget only needs to be given
+/// once
+pub(crate) fn parse(args: &[String]) -> anyhow::Result<Vec<Stack>> {
+    let mut stacks: Vec<Stack> = vec![];
+    for arg in args {
+        let (head, pattern) = arg
+            .split_once('=')
+            .ok_or_else(|| anyhow!("invalid stack `{}`; expected `NAME[@BUDGET]=PATTERN`", arg))?;
+        let (name, budget) = match head.split_once('@') {
+            Some((name, budget)) => {
+                let budget = budget
+                    .parse::<u64>()
+                    .map_err(|e| anyhow!("invalid budget in stack `{}`: {}", arg, e))?;
+                (name, Some(budget))
+            }
+            None => (head, None),
+        };
+        if name.is_empty() {
+            bail!("invalid stack `{}`; the name is empty", arg);
+        }
+        add(&mut stacks, name, budget, Pattern::new(pattern)?)?;
+    }
+
+    Ok(stacks)
+}
+
+/// Returns the name of the stack in a `--stack` argument
+pub(crate) fn name(arg: &str) -> &str {
+    let head = arg.split_once('=').map(|(head, _)| head).unwrap_or(arg);
+    head.split_once('@').map(|(name, _)| name).unwrap_or(head)
+}
+
+/// Adds `root` to the roots of stack `name`, creating the stack if it doesn't exist
+pub(crate) fn add(
+    stacks: &mut Vec<Stack>,
+    name: &str,
+    budget: Option<u64>,
+    root: Pattern,
+) -> anyhow::Result<()> {
+    if let Some(stack) = stacks.iter_mut().find(|stack| stack.name == name) {
+        match (stack.budget, budget) {
+            (Some(old), Some(new)) if old != new => bail!(
+                "stack `{}` has two different budgets: {} and {}",
+                name,
+                old,
+                new
+            ),
+            (None, Some(_)) => stack.budget = budget,
+            _ => {}
+        }
+        stack.roots.push(root);
+    } else {
+        stacks.push(Stack {
+            name: name.to_owned(),
+            budget,
+            roots: vec![root],
+        });
+    }
+
+    Ok(())
+}
+
+/// Worst-case usage of a stack shared by `roots`
+///
+/// The roots of a stack preempt each other, e.g. an interrupt handler preempts `main`, so in the
+/// worst case all of them are active at the same time and their usages add up, plus the `frame`
+/// bytes the hardware pushes onto the stack each time a root preempts another one. This assumes
+/// that every root can preempt all the others; roots at the same priority level can't, so the
+/// result may overestimate the usage. Returns `None` if `roots` is empty
+fn worst_case(g: &Graph<Node, Edge>, roots: &[NodeIndex], frame: u64) -> Option<Max> {
+    let usage = |root: &NodeIndex| g[*root].max.unwrap_or(Max::LowerBound(0));
+
+    let (first, rest) = roots.split_first()?;
+    Some(rest.iter().fold(usage(first), |total, root| {
+        total + usage(root) + Max::Exact(frame)
+    }))
+}
+
+/// Prints to stderr the maximum usage of each stack and the functions that are reachable from
+/// more than one stack
+///
+/// `roots` contains the roots of each of the `stacks`; `frame` is the number of bytes pushed onto
+/// the stack when a root preempts another one (see `worst_case`). Returns `true` if any stack
+/// exceeds its budget
+pub(crate) fn check(
+    g: &Graph<Node, Edge>,
+    stacks: &[Stack],
+    roots: &[Vec<NodeIndex>],
+    frame: u64,
+) -> io::Result<bool> {
+    let stderr = io::stderr();
+    let mut stderr = stderr.lock();
+
+    let mut over_budget = false;
+    writeln!(stderr, "Stack Max Budget Roots")?;
+    for (stack, roots) in stacks.iter().zip(roots) {
+        let max = worst_case(g, roots, frame);
+
+        let budget = stack
+            .budget
+            .map(|budget| budget.to_string())
+            .unwrap_or_else(|| "-".to_owned());
+        let names = roots
+            .iter()
+            .map(|root| rustc_demangle::demangle(&g[*root].name).to_string())
+            .collect::<Vec<_>>();
+
+        let max = match max {
+            Some(max) => max,
+            None => {
+                writeln!(stderr, "{} ? {} -", stack.name, budget)?;
+                error!("none of the roots of stack `{}` was found", stack.name);
+                continue;
+            }
+        };
+
+        writeln!(
+            stderr,
+            "{} {} {} {}",
+            stack.name,
+            match max {
+                Max::Exact(n) => n.to_string(),
+                Max::LowerBound(n) => format!(">={}", n),
+            },
+            budget,
+            names.join(", ")
+        )?;
+
+        if let Some(budget) = stack.budget {
+            match max {
+                Max::Exact(n) | Max::LowerBound(n) if n > budget => {
+                    error!(
+                        "stack `{}` uses {} bytes; its budget is {} bytes",
+                        stack.name, n, budget
+                    );
+                    over_budget = true;
+                }
+                Max::LowerBound(n) => warn!(
+                    "stack `{}` uses at least {} bytes; it can't be verified that it fits in {} \
+                     bytes",
+                    stack.name, n, budget
+                ),
+                Max::Exact(_) => {}
+            }
+        }
+    }
+
+    // the stacks from which each function is reachable
+    let mut users = BTreeMap::<NodeIndex, Vec<&str>>::new();
+    for (stack, roots) in stacks.iter().zip(roots) {
+        let mut dfs = Dfs::empty(g);
+        for root in roots {
+            dfs.move_to(*root);
+            while let Some(node) = dfs.next(g) {
+                users.entry(node).or_default().push(&stack.name);
+            }
+        }
+    }
+
+    let mut shared = String::new();
+    let mut count = 0;
+    for (node, users) in users {
+        if users.len() > 1 && !g[node].dashed {
+            count += 1;
+            write!(
+                shared,
+                "\n  {}: {}",
+                users.join(", "),
+                rustc_demangle::demangle(&g[node].name)
+            )
+            .ok();
+        }
+    }
+    if count != 0 {
+        writeln!(
+            stderr,
+            "{} function(s) are reachable from more than one stack:{}",
+            count, shared
+        )?;
+    }
+
+    Ok(over_budget)
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn parse() {
+        let stacks = super::parse(&[
+            "core0@8192=main".to_owned(),
+            "core1=core1_main".to_owned(),
+            "core0=SysTick".to_owned(),
+        ])
+        .unwrap();
+
+        assert_eq!(stacks.len(), 2);
+        assert_eq!(stacks[0].name, "core0");
+        assert_eq!(stacks[0].budget, Some(8192));
+        assert_eq!(stacks[0].roots.len(), 2);
+        assert_eq!(stacks[1].budget, None);
+
+        assert!(super::parse(&["core0".to_owned()]).is_err());
+        assert!(super::parse(&["core0@8K=main".to_owned()]).is_err());
+    }
+
+    #[test]
+    fn preemption() {
+        use petgraph::graph::Graph;
+
+        use crate::{Max, Node};
+
+        let mut g = Graph::new();
+        let main = g.add_node(Node("main", Some(16), false));
+        let systick = g.add_node(Node("SysTick", Some(8), false));
+        let exti0 = g.add_node(Node("EXTI0", None, false));
+        g[main].max = Some(Max::Exact(100));
+        g[systick].max = Some(Max::Exact(40));
+        g[exti0].max = Some(Max::LowerBound(24));
+
+        // `SysTick` preempts `main`: both frames are on the stack, plus the exception frame
+        assert_eq!(
+            super::worst_case(&g, &[main, systick], 36),
+            Some(Max::Exact(176))
+        );
+        assert_eq!(
+            super::worst_case(&g, &[main, systick, exti0], 36),
+            Some(Max::LowerBound(236))
+        );
+        assert_eq!(super::worst_case(&g, &[main], 36), Some(Max::Exact(100)));
+        assert_eq!(super::worst_case(&g, &[], 36), None);
+    }
+}
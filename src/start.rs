@@ -0,0 +1,304 @@
+use core::{
+    cmp,
+    fmt::{self, Write as _},
+};
+use std::{
+    borrow::Cow,
+    collections::BTreeMap,
+    io::{self, Write},
+};
+
+use anyhow::anyhow;
+use log::error;
+use petgraph::graph::{Graph, NodeIndex};
+use regex::Regex;
+use xmas_elf::ElfFile;
+
+use crate::{dehash, max_value, Edge, Max, Node};
+
+/// A function name pattern, as given on the command line
+pub(crate) enum Pattern {
+    /// A symbol name or a demangled path, with or without hash and generic parameters
+    Name(String),
+    /// A pattern that contains the `*` and `?` wildcards; a lone `?` is the name of the node that
+    /// represents unknown functions
+    Glob(String),
+    /// A regular expression delimited by slashes, e.g. `/^app::(foo|bar)$/`
+    Regex(Regex),
+}
+
+impl Pattern {
+    pub(crate) fn new(pattern: &str) -> anyhow::Result<Self> {
+        if pattern.len() > 1 && pattern.starts_with('/') && pattern.ends_with('/') {
+            Regex::new(&pattern[1..pattern.len() - 1])
+                .map(Pattern::Regex)
+                .map_err(|e| anyhow!("invalid regular expression `{}`: {}", pattern, e))
+        } else if pattern != "?" && pattern.contains(['*', '?']) {
+            Ok(Pattern::Glob(pattern.to_owned()))
+        } else {
+            Ok(Pattern::Name(pattern.to_owned()))
+        }
+    }
+
+    /// Whether the function with symbol name `symbol` matches this pattern
+    ///
+    /// Patterns are matched against the symbol name, the demangled name, the demangled name without
+    /// hash and the demangled name without hash and without generic parameters. Regular
+    /// expressions are not anchored
+    pub(crate) fn matches(&self, symbol: &str) -> bool {
+        let names = names(symbol);
+
+        match self {
+            Pattern::Name(name) => names.iter().any(|s| s == name),
+            Pattern::Glob(pattern) => names.iter().any(|s| glob(pattern, s)),
+            Pattern::Regex(re) => names.iter().any(|s| re.is_match(s)),
+        }
+    }
+}
+
+impl fmt::Display for Pattern {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Pattern::Name(s) | Pattern::Glob(s) => f.write_str(s),
+            Pattern::Regex(re) => write!(f, "/{}/", re),
+        }
+    }
+}
+
+/// Returns the nodes that the start point `start` refers to
+///
+/// Globs and regular expressions select all the nodes they match. A name must refer to a single
+/// node unless `all` is set; if it's ambiguous the candidates are listed and no node is selected
+pub(crate) fn resolve(
+    start: &Pattern,
+    g: &Graph<Node, Edge>,
+    indices: &BTreeMap<Cow<str>, NodeIndex>,
+    all: bool,
+) -> Vec<NodeIndex> {
+    let hits = indices
+        .iter()
+        .filter(|(key, _)| start.matches(key))
+        .map(|(_, idx)| *idx)
+        .collect::<Vec<_>>();
+
+    if let Pattern::Name(name) = start {
+        // an exact symbol name is never ambiguous
+        if let Some(idx) = indices.get(name.as_str()) {
+            return vec![*idx];
+        }
+
+        if hits.len() > 1 && !all {
+            error!(
+                "multiple matches for `{}`; use a more specific name or `--all-matches` to select \
+                 all of them:{}",
+                name,
+                candidates(g, &hits)
+            );
+            return vec![];
+        }
+
+        if hits.is_empty() {
+            // suggest functions whose name contains the last path segment of `name`
+            let needle = name.rsplit("::").next().unwrap_or(name);
+            let similar = indices
+                .iter()
+                .filter(|(key, _)| names(key).iter().any(|s| s.contains(needle)))
+                .map(|(_, idx)| *idx)
+                .take(MAX_CANDIDATES)
+                .collect::<Vec<_>>();
+
+            if !similar.is_empty() {
+                error!(
+                    "start point `{}` not found; similar functions:{}",
+                    name,
+                    candidates(g, &similar)
+                );
+                return vec![];
+            }
+        }
+    }
+
+    if hits.is_empty() {
+        error!("start point `{}` not found", start);
+    }
+
+    hits
+}
+
+// upper bound on the number of suggestions printed when a start point is not found
+const MAX_CANDIDATES: usize = 10;
+
+// one function per line, with its address
+fn candidates(g: &Graph<Node, Edge>, nodes: &[NodeIndex]) -> String {
+    let mut list = String::new();
+    for idx in nodes {
+        let node = &g[*idx];
+        let address = node
+            .address
+            .map(|address| format!("{:#010x}", address))
+            .unwrap_or_else(|| "?".to_owned());
+
+        write!(
+            list,
+            "\n  {} {}",
+            address,
+            rustc_demangle::demangle(&node.name)
+        )
+        .ok();
+    }
+    list
+}
+
+// the names a function can be referred to by
+fn names(symbol: &str) -> Vec<String> {
+    let demangled = rustc_demangle::demangle(symbol).to_string();
+    let dehashed = dehash(&demangled).unwrap_or(&demangled).to_owned();
+    let generic_less = strip_generics(&dehashed);
+
+    let mut names = vec![symbol.to_owned()];
+    for name in [demangled, dehashed, generic_less] {
+        if !names.contains(&name) {
+            names.push(name);
+        }
+    }
+    names
+}
+
+// removes the generic parameters from a demangled path
+// e.g. `<app::Foo<u32> as app::Bar>::baz::<u8>` -> `<app::Foo as app::Bar>::baz`
+fn strip_generics(path: &str) -> String {
+    let mut out = String::with_capacity(path.len());
+    let mut depth = 0;
+    let mut prev = None;
+    for c in path.chars() {
+        if depth == 0 {
+            // a `<` at the start of a path, or after a space, opens a qualified path (`<T as
+            // Trait>`), not a list of generic parameters
+            let generic = c == '<'
+                && prev.map(|p: char| p.is_alphanumeric() || p == '_' || p == ':') == Some(true);
+
+            if generic {
+                depth = 1;
+                // remove the `::` of the turbofish
+                if out.ends_with("::") {
+                    out.truncate(out.len() - 2);
+                }
+            } else {
+                out.push(c);
+            }
+        } else if c == '<' {
+            depth += 1;
+        } else if c == '>' && prev != Some('-') {
+            // NOTE `->` appears in function pointer types like `fn() -> u32`
+            depth -= 1;
+        }
+
+        prev = Some(c);
+    }
+    out
+}
+
+/// Returns the addresses of the exception and interrupt handlers listed in the vector table of an
+/// ARM Cortex-M program
+///
+/// The vector table is expected to be in a section named `.vector_table`, as it is in programs
+/// linked with `cortex-m-rt`. The first entry, the initial value of the stack pointer, is skipped
+pub(crate) fn vector_table(elf: &ElfFile) -> Vec<u64> {
+    let sect = if let Some(sect) = elf.find_section_by_name(".vector_table") {
+        sect
+    } else {
+        return vec![];
+    };
+
+    sect.raw_data(elf)
+        .chunks_exact(4)
+        .skip(1)
+        .filter_map(|word| {
+            let address = u32::from_le_bytes([word[0], word[1], word[2], word[3]]);
+
+            // clear the thumb bit; unused entries are zero
+            if address == 0 {
+                None
+            } else {
+                Some(u64::from(address & !1))
+            }
+        })
+        .collect()
+}
+
+/// Prints the maximum stack usage of each root to stderr, largest first
+pub(crate) fn summary(g: &Graph<Node, Edge>, roots: &[NodeIndex]) -> io::Result<()> {
+    let stderr = io::stderr();
+    let mut stderr = stderr.lock();
+
+    let mut roots = roots.to_owned();
+    roots.sort_by_key(|idx| cmp::Reverse(max_value(g[*idx].max)));
+
+    writeln!(stderr, "Max Root")?;
+    for idx in roots {
+        let node = &g[idx];
+        let max = match node.max {
+            Some(Max::Exact(n)) => n.to_string(),
+            Some(Max::LowerBound(n)) => format!(">={}", n),
+            None => "?".to_owned(),
+        };
+
+        writeln!(stderr, "{} {}", max, rustc_demangle::demangle(&node.name))?;
+    }
+
+    Ok(())
+}
+
+// `*` matches any sequence of characters, `?` matches a single character
+fn glob(pattern: &str, text: &str) -> bool {
+    let pattern = pattern.chars().collect::<Vec<_>>();
+    let text = text.chars().collect::<Vec<_>>();
+
+    let (mut p, mut t) = (0, 0);
+    // position of the last `*` in `pattern` and the position in `text` it's currently matched up to
+    let mut star = None;
+    while t < text.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == text[t]) {
+            p += 1;
+            t += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            star = Some((p, t));
+            p += 1;
+        } else if let Some((sp, st)) = star {
+            // backtrack: let the last `*` match one more character
+            p = sp + 1;
+            t = st + 1;
+            star = Some((sp, st + 1));
+        } else {
+            return false;
+        }
+    }
+
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn glob() {
+        assert!(super::glob("app::*", "app::main"));
+        assert!(super::glob("*::handler", "app::SysTick::handler"));
+        assert!(super::glob("app::fo?", "app::foo"));
+        assert!(super::glob("*", ""));
+        assert!(!super::glob("app::*", "core::fmt::write"));
+        assert!(!super::glob("app::fo?", "app::fooo"));
+    }
+
+    #[test]
+    fn strip_generics() {
+        assert_eq!(super::strip_generics("app::foo::<u32>"), "app::foo");
+        assert_eq!(
+            super::strip_generics("core::ptr::drop_in_place<app::Foo<u8>>"),
+            "core::ptr::drop_in_place"
+        );
+        assert_eq!(
+            super::strip_generics("<app::Foo<fn() -> u32> as app::Bar>::baz::<u8>"),
+            "<app::Foo as app::Bar>::baz"
+        );
+    }
+}
@@ -1,5 +1,14 @@
+/// A branch instruction as a `(site, target)` pair, where both are offsets from the start of the
+/// subroutine: `site` is the location of the branch instruction and `target` is its destination
+pub type Branch = (i32, i32);
+
+/// A `B` instruction: a `Branch` plus the stack frame size, in bytes, at the branch instruction,
+/// if known. When the `B` instruction is a tail call this is the stack the callee runs on top of
+pub type Jump = (i32, i32, Option<u64>);
+
 /// Analyzes a subroutine and returns all the `BL` and `B` instructions in it, plus whether this
-/// function performs an indirect function call or not
+/// function performs an indirect function call or not and whether it calls non-secure code
+/// (`BLXNS`, or `BXNS` to an address other than the return address)
 // NOTE we assume that `bytes` is always valid input so all errors are bugs
 // Reference: ARMv7-M Architecture Reference Manual (ARM DDI 0403E.b)
 // Reference: ARMv6-M Architecture Reference Manual (ARM DDI 0419D)
@@ -8,7 +17,7 @@ pub fn analyze(
     address: u32,
     v7: bool,
     tags: &[(u32, Tag)],
-) -> (Vec<i32>, Vec<i32>, bool, bool, Option<u64>) {
+) -> (Vec<Branch>, Vec<Jump>, bool, bool, Option<u64>, bool) {
     macro_rules! bug {
         ($first:expr) => {
             panic!(
@@ -42,6 +51,14 @@ pub fn analyze(
     // help with our main goal of analyzing trampolines.
     let mut stack = Some(0);
 
+    // we also track the size of the stack frame at each instruction so that we know how much stack
+    // is still in use when a tail call (`B` to another function) happens. Unlike `stack` this value
+    // decreases when stack is released (`pop`, `add sp, #4`, etc.). The instructions are decoded
+    // linearly so the frame size becomes unknown after the first instruction that doesn't fall
+    // through to the next one (`bx lr`, `pop {.., pc}`, unconditional `b`, etc.) or after an
+    // instruction that modifies the SP in a way we don't track (e.g. `mov sp, r7`)
+    let mut frame = Some(0u64);
+
     // we want to avoid writing a full blown decoder since we are only interested in a single type
     // of instruction. We know that instructions can be 16-bit or 32-bit so we'll only decode 16-bit
     // instructions and assume that the rest are 32-bit instructions.
@@ -49,6 +66,7 @@ pub fn analyze(
     let mut bls = vec![];
     let mut bs = vec![];
     let mut indirect = false;
+    let mut nonsecure = false;
     let mut halfwords = bytes.chunks_exact(2).zip(0i32..);
     while let Some((first, i)) = halfwords.next() {
         let start = address + 2 * i as u32;
@@ -101,12 +119,26 @@ pub fn analyze(
             continue;
         } else if matches(first, "0b1011_0000_0_xxxxxxx") {
             // A7.7.5  ADD (SP plus immediate) - T2
+            // e.g. 'b001            add     sp, #4'
+            let imm7 = first[0] & 0b0111_1111;
+            let imm32 = u32::from(imm7) << 2;
+
+            frame = frame.and_then(|frame| frame.checked_sub(u64::from(imm32)));
+
             continue;
         } else if matches(first, "0b01000100_x_1101_xxx") {
             // A7.7.6  ADD (SP plus register) - T1
+            let rdm = ((first[0] >> 4) & 0b1000) | (first[0] & 0b111);
+            if rdm == 0b1101 {
+                // add sp, sp
+                frame = None;
+            }
+
             continue;
         } else if matches(first, "0b01000100_1_xxxx_101") {
             // A7.7.6  ADD (SP plus register) - T2
+            frame = None;
+
             continue;
         } else if matches(first, "0b1010_0_xxx_xxxxxxxx") {
             // A7.7.7  ADR - T1
@@ -148,7 +180,7 @@ pub fn analyze(
                 stack = None;
             }
 
-            bs.push(imm32);
+            bs.push((2 * i, imm32, frame));
         } else if matches(first, "0b11100_xxxxxxxxxxx") {
             // A7.7.12  B - T2
             let imm11 = (i32::from(first[1] & 0b111) << 8) | first[0] as i32;
@@ -164,7 +196,9 @@ pub fn analyze(
                 stack = None;
             }
 
-            bs.push(imm32);
+            bs.push((2 * i, imm32, frame));
+            // unconditional branch
+            frame = None;
         } else if matches(first, "0b010000_1110_xxx_xxx") {
             // A7.7.16  BIC (register) - T1
             continue;
@@ -182,6 +216,21 @@ pub fn analyze(
             if rm != 0b1110 {
                 indirect = true;
             }
+
+            frame = None;
+        } else if matches(first, "0b010001_11_1_xxxx_100") {
+            // C2.4.27  BLXNS - T1 (in ARMv8-M-ARM)
+            nonsecure = true;
+        } else if matches(first, "0b010001_11_0_xxxx_100") {
+            // C2.4.29  BXNS - T1 (in ARMv8-M-ARM)
+            let rm = (first[0] >> 3) & 0b1111;
+
+            // `bxns lr` is the return from a secure entry function
+            if rm != 0b1110 {
+                nonsecure = true;
+            }
+
+            frame = None;
         } else if v7 && matches(first, "0b1011_x_0_x_1_xxxxx_xxx") {
             // A7.7.21  CBNZ, CBZ - T1
             continue;
@@ -256,6 +305,12 @@ pub fn analyze(
             continue;
         } else if matches(first, "0b010001_10_x_xxxx_xxx") {
             // A7.7.76  MOV (register) - T1
+            let rd = ((first[0] >> 4) & 0b1000) | (first[0] & 0b111);
+            if rd == 0b1101 || rd == 0b1111 {
+                // e.g. 'mov sp, r7'
+                frame = None;
+            }
+
             continue;
         } else if matches(first, "0b000_00_00000_xxx_xxx") {
             // A7.7.76  MOV (register) - T2
@@ -275,6 +330,18 @@ pub fn analyze(
             continue;
         } else if matches(first, "0b1011_1_10_x_xxxxxxxx") {
             // A7.7.98  POP - T1
+            // e.g. 'bd80            pop     {r7, pc}'
+            let p = first[1] & 1;
+            let register_list = first[0];
+            let register = (u16::from(p) << 15) | u16::from(register_list);
+
+            frame = frame.and_then(|frame| frame.checked_sub(4 * u64::from(register.count_ones())));
+
+            if p == 1 {
+                // return
+                frame = None;
+            }
+
             continue;
         } else if matches(first, "0b1011_0_10_x_xxxxxxxx") {
             // A7.7.99  PUSH - T1
@@ -287,6 +354,9 @@ pub fn analyze(
             if let Some(stack) = stack.as_mut() {
                 *stack += 4 * u64::from(register.count_ones());
             }
+            if let Some(frame) = frame.as_mut() {
+                *frame += 4 * u64::from(register.count_ones());
+            }
 
             continue;
         } else if matches(first, "0b1011_1010_00_xxx_xxx") {
@@ -354,6 +424,9 @@ pub fn analyze(
             if let Some(stack) = stack.as_mut() {
                 *stack += u64::from(imm32);
             }
+            if let Some(frame) = frame.as_mut() {
+                *frame += u64::from(imm32);
+            }
 
             continue;
         } else if matches(first, "0b1011_0010_01_xxx_xxx") {
@@ -385,7 +458,10 @@ pub fn analyze(
 
             const SP: u8 = 0b1101;
 
-            if v7
+            if matches(first, "0b1110_1001_0111_1111") && matches(second, "0b1110_1001_0111_1111") {
+                // C2.4.155  SG - T1 (in ARMv8-M-ARM)
+                continue;
+            } else if v7
                 && matches(first, "0b11101_00_100_x_0_xxxx")
                 && matches(second, "0b0_x_0_xxxxxxxxxxxxx")
             {
@@ -403,6 +479,9 @@ pub fn analyze(
                     if let Some(stack) = stack.as_mut() {
                         *stack += 4 * u64::from(registers.count_ones());
                     }
+                    if let Some(frame) = frame.as_mut() {
+                        *frame += 4 * u64::from(registers.count_ones());
+                    }
                 }
             } else if v7
                 && matches(first, "0b11110_x_0_1101_x_1101")
@@ -423,6 +502,9 @@ pub fn analyze(
                     if let Some(stack) = stack.as_mut() {
                         *stack += u64::from(imm32);
                     }
+                    if let Some(frame) = frame.as_mut() {
+                        *frame += u64::from(imm32);
+                    }
                 }
             } else if v7
                 && matches(first, "0b1110_110_1_0_x_1_0_1101")
@@ -437,6 +519,9 @@ pub fn analyze(
                 if let Some(stack) = stack.as_mut() {
                     *stack += u64::from(imm32);
                 }
+                if let Some(frame) = frame.as_mut() {
+                    *frame += u64::from(imm32);
+                }
             } else if v7
                 && matches(first, "0b1110_110_1_0_x_1_0_1101")
                 && matches(second, "0bxxxx_1010_xxxxxxxx")
@@ -450,6 +535,9 @@ pub fn analyze(
                 if let Some(stack) = stack.as_mut() {
                     *stack += u64::from(imm32);
                 }
+                if let Some(frame) = frame.as_mut() {
+                    *frame += u64::from(imm32);
+                }
             } else if v7
                 && matches(first, "0b11110_x_xxxxxxxxxx")
                 && matches(second, "0b10_x_0_x_xxxxxxxxxxx")
@@ -484,7 +572,7 @@ pub fn analyze(
                     stack = None;
                 }
 
-                bs.push(imm32);
+                bs.push((2 * i, imm32, frame));
             } else if v7
                 && matches(first, "0b11110_x_xxxxxxxxxx")
                 && matches(second, "0b10_x_1_x_xxxxxxxxxxx")
@@ -517,7 +605,9 @@ pub fn analyze(
                     stack = None;
                 }
 
-                bs.push(imm32);
+                bs.push((2 * i, imm32, frame));
+                // unconditional branch
+                frame = None;
             } else if matches(first, "0b11110_x_xxxxxxxxxx")
                 && matches(second, "0b11_x_1_x_xxxxxxxxxxx")
             {
@@ -543,7 +633,7 @@ pub fn analyze(
                 // accordingly
                 imm32 += 2 * i + 4;
 
-                bls.push(imm32);
+                bls.push((2 * i, imm32));
             } else if matches(first, "0b11111_0000100_xxxx")
                 && matches(second, "0bxxxx_1x01_xxxxxxxx")
             {
@@ -560,7 +650,90 @@ pub fn analyze(
                     if let Some(stack) = stack.as_mut() {
                         *stack += u64::from(imm32);
                     }
+                    if let Some(frame) = frame.as_mut() {
+                        *frame += u64::from(imm32);
+                    }
+                }
+            } else if v7
+                && matches(first, "0b11101_00_010_1_1_1101")
+                && matches(second, "0bxx_0_xxxxxxxxxxxxx")
+            {
+                // A7.7.40  LDM, LDMIA, LDMFD - T2
+                // e.g. 'e8bd 81f0       pop.w   {r4, r5, r6, r7, r8, pc}'
+                let registers = (u16::from(second[1]) << 8) | u16::from(second[0]);
+
+                frame = frame
+                    .and_then(|frame| frame.checked_sub(4 * u64::from(registers.count_ones())));
+
+                if registers & (1 << 15) != 0 {
+                    // return
+                    frame = None;
+                }
+            } else if v7
+                && matches(first, "0b11111_0000101_1101")
+                && matches(second, "0bxxxx_1_0_1_1_xxxxxxxx")
+            {
+                // A7.7.43  LDR (immediate) - T4
+                // (post-increment)
+                // e.g. 'f85d bb04       ldr     r11, [sp], #4'
+                let rt = second[1] >> 4;
+                let imm8 = second[0];
+
+                frame = frame.and_then(|frame| frame.checked_sub(u64::from(imm8)));
+
+                if rt == 0b1111 {
+                    // return
+                    frame = None;
                 }
+            } else if v7
+                && matches(first, "0b1110_110_0_1_x_1_1_1101")
+                && matches(second, "0bxxxx_101x_xxxxxxxx")
+            {
+                // A7.7.248      VPOP - T1, T2
+                // e.g. 'ecbd 8b02       vpop    {d8}'
+                let imm8 = second[0];
+                let imm32 = u32::from(imm8) << 2;
+
+                frame = frame.and_then(|frame| frame.checked_sub(u64::from(imm32)));
+            } else if v7
+                && matches(first, "0b11110_x_0_1000_x_1101")
+                && matches(second, "0b0_xxx_1101_xxxxxxxx")
+            {
+                // A7.7.5  ADD (SP plus immediate) - T3
+                // e.g. 'f50d 7d02       add.w   sp, sp, #520    ; 0x208'
+                let imm8 = second[0];
+                let imm3 = (second[1] >> 4) & 0b0111;
+                let i = (first[1] >> 2) & 1;
+                let imm32 = thumb_expand_imm(
+                    (u16::from(i) << 11) | (u16::from(imm3) << 8) | u16::from(imm8),
+                );
+
+                frame = frame.and_then(|frame| frame.checked_sub(u64::from(imm32)));
+            } else if v7
+                && matches(first, "0b11110_x_1_0000_0_1101")
+                && matches(second, "0b0_xxx_1101_xxxxxxxx")
+            {
+                // A7.7.5  ADD (SP plus immediate) - T4
+                // e.g. 'f20d 4d04       addw    sp, sp, #1028   ; 0x404'
+                let imm8 = second[0];
+                let imm3 = (second[1] >> 4) & 0b0111;
+                let i = (first[1] >> 2) & 1;
+                let imm32 = (u16::from(i) << 11) | (u16::from(imm3) << 8) | u16::from(imm8);
+
+                frame = frame.and_then(|frame| frame.checked_sub(u64::from(imm32)));
+            } else if v7
+                && (matches(first, "0b11110_xxxxxxxxxxx") || matches(first, "0b11101_01_xxxxxxxxx"))
+                && matches(second, "0b0_xxx_1101_xxxxxxxx")
+            {
+                // data processing instruction that writes to the SP and that's not tracked above
+                // e.g. 'f1a7 0d08       sub.w   sp, r7, #8'
+                frame = None;
+            } else if v7
+                && matches(first, "0b11101000_1101_xxxx")
+                && matches(second, "0b1111_0000_000x_xxxx")
+            {
+                // A7.7.185      TBB, TBH - T1
+                frame = None;
             } else {
                 // some other 32-bit instruction
                 continue;
@@ -568,7 +741,7 @@ pub fn analyze(
         }
     }
 
-    (bls, bs, indirect, modifies_sp, stack)
+    (bls, bs, indirect, modifies_sp, stack, nonsecure)
 }
 
 fn matches(bytes: &[u8], pattern: &str) -> bool {
@@ -635,23 +808,23 @@ mod tests {
     fn sanity() {
         assert_eq!(
             super::analyze(&[0xff, 0xf7, 0xe4, 0xfe], 0, false, &[]).0,
-            vec![-568 + 4]
+            vec![(0, -568 + 4)]
         );
 
         assert_eq!(
             super::analyze(&[0x00, 0xf0, 0x2a, 0xfa], 0, false, &[]).0,
-            vec![1108 + 4]
+            vec![(0, 1108 + 4)]
         );
 
         assert_eq!(
             super::analyze(&[0x03, 0xe2], 0, false, &[]).1,
-            vec![1030 + 4]
+            vec![(0, 1030 + 4, Some(0))]
         );
 
         // UDF
         assert_eq!(
             super::analyze(&[0xfe, 0xde], 0, true, &[]),
-            (vec![], vec![], false, false, Some(0))
+            (vec![], vec![], false, false, Some(0), false)
         );
     }
 
@@ -692,4 +865,42 @@ mod tests {
         assert!(str.3);
         assert_eq!(str.4, Some(4));
     }
+
+    #[test]
+    fn tail_call_frame() {
+        // b580            push    {r7, lr}
+        // e8bd 4080       pop.w   {r7, lr}
+        // f000 b800       b.w     <next>
+        let released = super::analyze(
+            &[0x80, 0xb5, 0xbd, 0xe8, 0x80, 0x40, 0x00, 0xf0, 0x00, 0xb8],
+            0,
+            true,
+            &[],
+        );
+        assert_eq!(released.1, vec![(6, 10, Some(0))]);
+        assert_eq!(released.4, Some(8));
+
+        // b580            push    {r7, lr}
+        // f000 b800       b.w     <next>
+        let kept = super::analyze(&[0x80, 0xb5, 0x00, 0xf0, 0x00, 0xb8], 0, true, &[]);
+        assert_eq!(kept.1, vec![(2, 6, Some(8))]);
+
+        // bd80            pop     {r7, pc}
+        // f000 b800       b.w     <next>
+        let unknown = super::analyze(&[0x80, 0xbd, 0x00, 0xf0, 0x00, 0xb8], 0, true, &[]);
+        assert_eq!(unknown.1, vec![(2, 6, None)]);
+    }
+
+    #[test]
+    fn nonsecure() {
+        // e97f e97f       sg
+        let sg = super::analyze(&[0x7f, 0xe9, 0x7f, 0xe9], 0, true, &[]);
+        assert_eq!(sg, (vec![], vec![], false, false, Some(0), false));
+
+        // 478c            blxns   r1
+        assert!(super::analyze(&[0x8c, 0x47], 0, true, &[]).5);
+
+        // 4774            bxns    lr
+        assert!(!super::analyze(&[0x74, 0x47], 0, true, &[]).5);
+    }
 }
@@ -0,0 +1,146 @@
+//! Linker-generated veneers and long-branch thunks
+//!
+//! When the target of a `BL` or `B` instruction is out of range the linker redirects the branch to
+//! a small stub, placed next to the caller, that jumps to the real target. These stubs don't use
+//! the stack but, as they jump through a register, they look like indirect function calls
+
+use crate::start::Pattern;
+
+// GNU ld (`__foo_veneer`), armlink (`$Ven$TT$L$$foo`) and LLD (`__Thumbv7ABSLongThunk_foo`,
+// `__ThumbV7PILongThunk_foo`, `__ARMV7PILongThunk_foo`, etc.)
+const VENEERS: &[&str] = &["__*_veneer", "$Ven$*", "__*LongThunk_*", "__*ShortThunk_*"];
+
+/// Whether this symbol is a linker-generated veneer
+pub(crate) fn is_veneer(name: &str) -> bool {
+    VENEERS
+        .iter()
+        .any(|pattern| Pattern::new(pattern).expect("UNREACHABLE").matches(name))
+}
+
+/// Returns the name of the function that the veneer `name` jumps to, as encoded in the name of the
+/// veneer
+pub(crate) fn target_name(name: &str) -> Option<&str> {
+    if let Some(name) = name.strip_prefix("$Ven$") {
+        return name.split_once("$$").map(|(_, target)| target);
+    }
+
+    let name = name.strip_prefix("__")?;
+    if let Some(target) = name.strip_suffix("_veneer") {
+        return Some(target);
+    }
+
+    name.split_once("Thunk_").map(|(_, target)| target)
+}
+
+/// Decodes the Thumb machine code of a veneer located at `address` and returns the address it
+/// jumps to
+// Reference: ARMv7-M Architecture Reference Manual (ARM DDI 0403E.b)
+pub(crate) fn target(bytes: &[u8], address: u32) -> Option<u32> {
+    let halfwords = bytes
+        .chunks_exact(2)
+        .map(|hw| u16::from_le_bytes([hw[0], hw[1]]))
+        .collect::<Vec<_>>();
+    let word = |offset: u32| -> Option<u32> {
+        let offset = offset as usize;
+        let bytes = bytes.get(offset..offset + 4)?;
+        Some(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    };
+
+    let target = match halfwords.as_slice() {
+        // A7.7.43  LDR (literal) - T2
+        // e.g. `f8df f000  ldr.w pc, [pc, #0]` followed by the address of the target
+        [first, second, ..] if first & 0xff7f == 0xf85f && second & 0xf000 == 0xf000 => {
+            let imm12 = u32::from(second & 0x0fff);
+            // the literal is addressed relative to `Align(PC, 4)`
+            let base = ((address + 4) & !3) - address;
+            let offset = if first & 0x0080 != 0 {
+                base + imm12
+            } else {
+                base.checked_sub(imm12)?
+            };
+            word(offset)?
+        }
+
+        // A7.7.76  MOV (immediate) - T3 and A7.7.79  MOVT - T1, plus BX - T1
+        // e.g. `movw ip, #lo; movt ip, #hi; bx ip`
+        [w1, w2, t1, t2, rest @ ..] if w1 & 0xfbf0 == 0xf240 && t1 & 0xfbf0 == 0xf2c0 => {
+            let rd = (w2 >> 8) & 0xf;
+            if (t2 >> 8) & 0xf != rd {
+                return None;
+            }
+            let value = (u32::from(imm16(*t1, *t2)) << 16) | u32::from(imm16(*w1, *w2));
+            match rest {
+                // A7.7.4  ADD (register) - T2: `add ip, pc` makes the address PC-relative
+                [add, bx, ..]
+                    if *add == 0x4478 | ((rd & 0x8) << 4) | (rd & 0x7) && bx_(*bx, rd) =>
+                {
+                    value.wrapping_add(address + 4 * 2 + 4)
+                }
+                [bx, ..] if bx_(*bx, rd) => value,
+                _ => return None,
+            }
+        }
+
+        // A7.7.12  B - T4 (short thunks)
+        [first, second, ..] if first & 0xf800 == 0xf000 && second & 0xd000 == 0x9000 => {
+            let s = u32::from((first >> 10) & 1);
+            let imm10 = u32::from(first & 0x03ff);
+            let j1 = u32::from((second >> 13) & 1);
+            let j2 = u32::from((second >> 11) & 1);
+            let imm11 = u32::from(second & 0x07ff);
+            let i1 = !(j1 ^ s) & 1;
+            let i2 = !(j2 ^ s) & 1;
+            let imm32 = (s << 24) | (i1 << 23) | (i2 << 22) | (imm10 << 12) | (imm11 << 1);
+            let offset = ((imm32 << 7) as i32) >> 7;
+            (address as i32 + 4 + offset) as u32
+        }
+
+        _ => return None,
+    };
+
+    // clear the thumb bit
+    Some(target & !1)
+}
+
+// the immediate of a `MOVW` or `MOVT` instruction
+fn imm16(first: u16, second: u16) -> u16 {
+    let imm4 = first & 0xf;
+    let i = (first >> 10) & 1;
+    let imm3 = (second >> 12) & 0b111;
+    let imm8 = second & 0xff;
+    (imm4 << 12) | (i << 11) | (imm3 << 8) | imm8
+}
+
+// whether `halfword` is `bx rm`
+fn bx_(halfword: u16, rm: u16) -> bool {
+    halfword == 0x4700 | (rm << 3)
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn target() {
+        // __Thumbv7ABSLongThunk_far: movw r12, #1; movt r12, #8192; bx r12
+        let abs = [0x40, 0xf2, 0x01, 0x0c, 0xc2, 0xf2, 0x00, 0x0c, 0x60, 0x47];
+        assert_eq!(super::target(&abs, 0x1008), Some(0x2000_0000));
+
+        // __ThumbV7PILongThunk_far: movw r12, #61421; movt r12, #8191; add r12, pc; bx r12
+        let pi = [
+            0x4e, 0xf6, 0xed, 0x7c, 0xc1, 0xf6, 0xff, 0x7c, 0xfc, 0x44, 0x60, 0x47,
+        ];
+        assert_eq!(super::target(&pi, 0x1008), Some(0x2000_0000));
+
+        // __far_veneer: ldr.w pc, [pc, #0]; .word far
+        let ldr = [0xdf, 0xf8, 0x00, 0xf0, 0x01, 0x00, 0x00, 0x20];
+        assert_eq!(super::target(&ldr, 0x1008), Some(0x2000_0000));
+
+        assert_eq!(super::target(&[0x70, 0x47], 0x1008), None);
+    }
+
+    #[test]
+    fn target_name() {
+        assert_eq!(super::target_name("__far_veneer"), Some("far"));
+        assert_eq!(super::target_name("__ARMV7PILongThunk_far"), Some("far"));
+        assert_eq!(super::target_name("$Ven$TT$L$$far"), Some("far"));
+    }
+}
@@ -0,0 +1,308 @@
+use core::fmt;
+use std::io::{self, Write};
+
+use petgraph::graph::{Graph, NodeIndex};
+
+use crate::{Edge, Local, Max, Node};
+
+/// A typed attribute value
+enum Value {
+    Str(String),
+    Long(u64),
+    Bool(bool),
+}
+
+impl Value {
+    fn graphml_type(&self) -> &'static str {
+        match self {
+            Value::Str(_) => "string",
+            Value::Long(_) => "long",
+            Value::Bool(_) => "boolean",
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Str(s) => Escaped(s).fmt(f),
+            Value::Long(n) => n.fmt(f),
+            Value::Bool(b) => b.fmt(f),
+        }
+    }
+}
+
+// (name, type) of the node attributes, in the order `node_attrs` returns them
+const NODE_ATTRS: &[(&str, &str)] = &[
+    ("mangled", "string"),
+    ("demangled", "string"),
+    ("local", "long"),
+    ("max", "long"),
+    ("bound", "string"),
+    ("indirect", "boolean"),
+    ("assumed", "boolean"),
+    ("scc", "long"),
+    ("address", "long"),
+    ("location", "string"),
+];
+
+const EDGE_ATTRS: &[(&str, &str)] = &[
+    ("origin", "string"),
+    ("calls", "long"),
+    ("tail", "boolean"),
+    ("address", "long"),
+    ("location", "string"),
+    ("sites", "string"),
+];
+
+// missing values are represented as `None`
+fn node_attrs(node: &Node, scc: Option<usize>) -> Vec<Option<Value>> {
+    let (max, bound) = match node.max {
+        Some(Max::Exact(n)) => (Some(n), Some("exact")),
+        Some(Max::LowerBound(n)) => (Some(n), Some("lower")),
+        None => (None, None),
+    };
+
+    vec![
+        Some(Value::Str(
+            node.mangled.as_ref().unwrap_or(&node.name).to_string(),
+        )),
+        Some(Value::Str(rustc_demangle::demangle(&node.name).to_string())),
+        match node.local {
+            Local::Exact(n) => Some(Value::Long(n)),
+            Local::Unknown => None,
+        },
+        max.map(Value::Long),
+        bound.map(|bound| Value::Str(bound.to_owned())),
+        Some(Value::Bool(node.dashed)),
+        Some(Value::Bool(node.assumed)),
+        scc.map(|scc| Value::Long(scc as u64)),
+        node.address.map(Value::Long),
+        node.loc.as_ref().map(|loc| Value::Str(loc.to_string())),
+    ]
+}
+
+// `address` and `location` are those of the first call site; `sites` lists all of them
+fn edge_attrs(edge: &Edge) -> Vec<Option<Value>> {
+    let first = edge.sites.first();
+
+    vec![
+        Some(Value::Str(edge.origin.to_string())),
+        Some(Value::Long(edge.sites.len() as u64)),
+        Some(Value::Bool(edge.is_tail_call())),
+        first.and_then(|site| site.address).map(Value::Long),
+        first
+            .and_then(|site| site.loc.as_ref())
+            .map(|loc| Value::Str(loc.to_string())),
+        if edge.sites.is_empty() {
+            None
+        } else {
+            Some(Value::Str(
+                edge.sites
+                    .iter()
+                    .map(|site| site.to_string())
+                    .collect::<Vec<_>>()
+                    .join("; "),
+            ))
+        },
+    ]
+}
+
+fn sccs(g: &Graph<Node, Edge>, cycles: &[Vec<NodeIndex>]) -> Vec<Option<usize>> {
+    let mut scc = vec![None; g.node_count()];
+    for (i, cycle) in cycles.iter().enumerate() {
+        for node in cycle {
+            scc[node.index()] = Some(i);
+        }
+    }
+    scc
+}
+
+// the results are only valid if the excluded functions are never called
+fn exclusions(excluded: &[String]) -> String {
+    format!(
+        "excluded: {}; the results assume that these functions are never called",
+        excluded.join(", ")
+    )
+}
+
+pub(crate) fn graphml(
+    g: &Graph<Node, Edge>,
+    cycles: &[Vec<NodeIndex>],
+    excluded: &[String],
+) -> io::Result<()> {
+    let stdout = io::stdout();
+    let mut stdout = stdout.lock();
+
+    writeln!(stdout, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+    writeln!(
+        stdout,
+        r#"<graphml xmlns="http://graphml.graphdrawing.org/xmlns">"#
+    )?;
+
+    for (name, ty) in NODE_ATTRS {
+        writeln!(
+            stdout,
+            r#"  <key id="n_{0}" for="node" attr.name="{0}" attr.type="{1}"/>"#,
+            name, ty
+        )?;
+    }
+    for (name, ty) in EDGE_ATTRS {
+        writeln!(
+            stdout,
+            r#"  <key id="e_{0}" for="edge" attr.name="{0}" attr.type="{1}"/>"#,
+            name, ty
+        )?;
+    }
+    if !excluded.is_empty() {
+        writeln!(
+            stdout,
+            r#"  <key id="g_excluded" for="graph" attr.name="excluded" attr.type="string"/>"#
+        )?;
+    }
+
+    writeln!(stdout, r#"  <graph id="G" edgedefault="directed">"#)?;
+    if !excluded.is_empty() {
+        writeln!(
+            stdout,
+            "    <desc>{}</desc>",
+            Escaped(&exclusions(excluded))
+        )?;
+        writeln!(
+            stdout,
+            r#"    <data key="g_excluded">{}</data>"#,
+            Escaped(&excluded.join(", "))
+        )?;
+    }
+
+    let scc = sccs(g, cycles);
+    for idx in g.node_indices() {
+        writeln!(stdout, r#"    <node id="n{}">"#, idx.index())?;
+        for ((name, ty), value) in NODE_ATTRS.iter().zip(node_attrs(&g[idx], scc[idx.index()])) {
+            if let Some(value) = value {
+                debug_assert_eq!(*ty, value.graphml_type());
+                writeln!(stdout, r#"      <data key="n_{}">{}</data>"#, name, value)?;
+            }
+        }
+        writeln!(stdout, "    </node>")?;
+    }
+
+    for (i, edge) in g.raw_edges().iter().enumerate() {
+        writeln!(
+            stdout,
+            r#"    <edge id="e{}" source="n{}" target="n{}">"#,
+            i,
+            edge.source().index(),
+            edge.target().index()
+        )?;
+        for ((name, ty), value) in EDGE_ATTRS.iter().zip(edge_attrs(&edge.weight)) {
+            if let Some(value) = value {
+                debug_assert_eq!(*ty, value.graphml_type());
+                writeln!(stdout, r#"      <data key="e_{}">{}</data>"#, name, value)?;
+            }
+        }
+        writeln!(stdout, "    </edge>")?;
+    }
+
+    writeln!(stdout, "  </graph>")?;
+    writeln!(stdout, "</graphml>")
+}
+
+pub(crate) fn gexf(
+    g: &Graph<Node, Edge>,
+    cycles: &[Vec<NodeIndex>],
+    excluded: &[String],
+) -> io::Result<()> {
+    let stdout = io::stdout();
+    let mut stdout = stdout.lock();
+
+    writeln!(stdout, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+    writeln!(
+        stdout,
+        r#"<gexf xmlns="http://gexf.net/1.3" version="1.3">"#
+    )?;
+    if !excluded.is_empty() {
+        writeln!(stdout, "  <meta>")?;
+        writeln!(
+            stdout,
+            "    <description>{}</description>",
+            Escaped(&exclusions(excluded))
+        )?;
+        writeln!(stdout, "  </meta>")?;
+    }
+    writeln!(stdout, r#"  <graph defaultedgetype="directed">"#)?;
+
+    for (class, attrs) in [("node", NODE_ATTRS), ("edge", EDGE_ATTRS)] {
+        writeln!(stdout, r#"    <attributes class="{}">"#, class)?;
+        for (i, (name, ty)) in attrs.iter().enumerate() {
+            writeln!(
+                stdout,
+                r#"      <attribute id="{}" title="{}" type="{}"/>"#,
+                i, name, ty
+            )?;
+        }
+        writeln!(stdout, "    </attributes>")?;
+    }
+
+    let scc = sccs(g, cycles);
+    writeln!(stdout, "    <nodes>")?;
+    for idx in g.node_indices() {
+        let node = &g[idx];
+        writeln!(
+            stdout,
+            r#"      <node id="{}" label="{}">"#,
+            idx.index(),
+            Escaped(&rustc_demangle::demangle(&node.name).to_string())
+        )?;
+        attvalues(&mut stdout, node_attrs(node, scc[idx.index()]))?;
+        writeln!(stdout, "      </node>")?;
+    }
+    writeln!(stdout, "    </nodes>")?;
+
+    writeln!(stdout, "    <edges>")?;
+    for (i, edge) in g.raw_edges().iter().enumerate() {
+        writeln!(
+            stdout,
+            r#"      <edge id="{}" source="{}" target="{}">"#,
+            i,
+            edge.source().index(),
+            edge.target().index()
+        )?;
+        attvalues(&mut stdout, edge_attrs(&edge.weight))?;
+        writeln!(stdout, "      </edge>")?;
+    }
+    writeln!(stdout, "    </edges>")?;
+
+    writeln!(stdout, "  </graph>")?;
+    writeln!(stdout, "</gexf>")
+}
+
+fn attvalues(w: &mut impl Write, values: Vec<Option<Value>>) -> io::Result<()> {
+    writeln!(w, "        <attvalues>")?;
+    for (i, value) in values.into_iter().enumerate() {
+        if let Some(value) = value {
+            writeln!(w, r#"          <attvalue for="{}" value="{}"/>"#, i, value)?;
+        }
+    }
+    writeln!(w, "        </attvalues>")
+}
+
+// escapes XML special characters
+struct Escaped<'a>(&'a str);
+
+impl fmt::Display for Escaped<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for c in self.0.chars() {
+            match c {
+                '<' => f.write_str("&lt;")?,
+                '>' => f.write_str("&gt;")?,
+                '&' => f.write_str("&amp;")?,
+                '"' => f.write_str("&quot;")?,
+                '\'' => f.write_str("&apos;")?,
+                c => fmt::Write::write_char(f, c)?,
+            }
+        }
+
+        Ok(())
+    }
+}